@@ -0,0 +1,42 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use winevents_xml_transform::{from_string, ToCEF};
+
+const XML_FILES: [&str; 3] = [
+    include_str!("../data/winevt1.xml"),
+    include_str!("../data/winevt5.xml"),
+    include_str!("../data/winevt7.xml"),
+];
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+    group.throughput(Throughput::Elements(XML_FILES.len() as u64));
+    group.bench_function("from_string", |b| {
+        b.iter(|| {
+            for xml in XML_FILES.iter() {
+                black_box(from_string(xml.to_string()).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn to_cef(c: &mut Criterion) {
+    let events = XML_FILES
+        .iter()
+        .map(|xml| from_string(xml.to_string()).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("cef");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    group.bench_function("to_cef", |b| {
+        b.iter(|| {
+            for e in events.iter() {
+                black_box(e.to_cef());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, deserialize, to_cef);
+criterion_main!(benches);
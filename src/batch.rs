@@ -0,0 +1,247 @@
+use chrono::format::{Item, StrftimeItems};
+use serde_json::{json, Value};
+
+use crate::{
+    ecs::ToECS,
+    error::{Error, Result},
+    ocsf::ToOCSF,
+    Event,
+};
+
+/// Document sent for each event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// The event serialized to JSON as it is
+    Json,
+    /// The Elastic Common Schema document of the event
+    Ecs,
+    /// The OCSF event
+    Ocsf,
+}
+
+impl Payload {
+    fn document(self, evt: &Event) -> Result<Value> {
+        Ok(match self {
+            Payload::Json => serde_json::to_value(evt)?,
+            Payload::Ecs => evt.to_ecs(),
+            Payload::Ocsf => evt.to_ocsf(),
+        })
+    }
+}
+
+/// Options of the Splunk HTTP Event Collector payload
+#[derive(Debug, Clone)]
+pub struct HecOptions {
+    /// Document of the events, the event JSON by default
+    pub payload: Payload,
+    /// Source type of the events, the event provider (or channel) by default
+    pub sourcetype: Option<String>,
+    /// Splunk index, the one of the HEC token by default
+    pub index: Option<String>,
+}
+
+impl Default for HecOptions {
+    fn default() -> Self {
+        Self { payload: Payload::Json, sourcetype: None, index: None }
+    }
+}
+
+/// Action of the Elasticsearch bulk requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkAction {
+    /// Index the document, replacing any with the same `_id`
+    Index,
+    /// Create the document (required by data streams)
+    Create,
+}
+
+/// Options of the Elasticsearch `_bulk` payload
+#[derive(Debug, Clone)]
+pub struct BulkOptions {
+    /// Document of the events, the ECS document by default
+    pub payload: Payload,
+    /// Index name pattern, `winevents-{channel}-{date}` by default.
+    /// `{channel}`, `{provider}` and `{date}` are replaced with those of the event,
+    /// and the name is made a valid index name (lower case, no `\/*?"<>|,#` nor space)
+    pub index: String,
+    /// `strftime` format of `{date}`, `%Y.%m.%d` by default
+    pub date_format: String,
+    /// Bulk action, `index` by default
+    pub action: BulkAction,
+    /// Set the document `_id` from the computer, channel and record id,
+    /// so that sending an event twice does not duplicate it
+    pub with_id: bool,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            payload: Payload::Ecs,
+            index: "winevents-{channel}-{date}".to_string(),
+            date_format: "%Y.%m.%d".to_string(),
+            action: BulkAction::Index,
+            with_id: false,
+        }
+    }
+}
+
+/// Serialize events into a Splunk HTTP Event Collector payload:
+/// one JSON object per event, separated by line feeds.
+/// Fails if an event cannot be serialized to JSON
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, to_splunk_hec, HecOptions};
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let body = to_splunk_hec(&[e], &HecOptions::default()).unwrap();
+/// assert!(body.starts_with(br#"{"time":1611659849.485,"host":"DESKTOP-G089JUF","#));
+/// ```
+pub fn to_splunk_hec<'a, I: IntoIterator<Item = &'a Event>>(events: I, opts: &HecOptions) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for evt in events {
+        let system = &evt.System;
+        let mut hec = json!({
+            "time": system.TimeCreated.timestamp_millis() as f64 / 1000.0,
+            "host": system.Computer,
+            "source": format!("WinEventLog:{}", system.Channel),
+            "sourcetype": opts.sourcetype.clone().unwrap_or_else(|| provider(evt).to_string()),
+        });
+        if let Some(index) = &opts.index {
+            hec["index"] = json!(index);
+        }
+        hec["event"] = opts.payload.document(evt)?;
+        serde_json::to_writer(&mut body, &hec)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
+/// Serialize events into an Elasticsearch `_bulk` request body (NDJSON):
+/// an action line and a document line per event.
+/// Fails if `date_format` is not a valid `strftime` format,
+/// or if an event cannot be serialized to JSON
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, to_elastic_bulk, BulkOptions};
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let body = to_elastic_bulk(&[e], &BulkOptions::default()).unwrap();
+/// assert!(body.starts_with(b"{\"index\":{\"_index\":\"winevents-security-2021.01.26\"}}\n"));
+/// ```
+pub fn to_elastic_bulk<'a, I: IntoIterator<Item = &'a Event>>(events: I, opts: &BulkOptions) -> Result<Vec<u8>> {
+    // Formatting the date with an unknown specifier would panic
+    if StrftimeItems::new(&opts.date_format).any(|item| matches!(item, Item::Error)) {
+        return Err(Error::DateFormat { format: opts.date_format.clone() });
+    }
+    let mut body = Vec::new();
+    for evt in events {
+        let system = &evt.System;
+        let mut meta = json!({ "_index": index_name(evt, opts) });
+        if opts.with_id {
+            meta["_id"] = json!(format!("{}-{}-{}", system.Computer, system.Channel, system.EventRecordID));
+        }
+        let action = match opts.action {
+            BulkAction::Index => json!({ "index": meta }),
+            BulkAction::Create => json!({ "create": meta }),
+        };
+        serde_json::to_writer(&mut body, &action)?;
+        body.push(b'\n');
+        serde_json::to_writer(&mut body, &opts.payload.document(evt)?)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
+/// Get the index of an event out of the index name pattern
+fn index_name(evt: &Event, opts: &BulkOptions) -> String {
+    let date = evt.System.TimeCreated.format(&opts.date_format).to_string();
+    let name = opts
+        .index
+        .replace("{channel}", &evt.System.Channel)
+        .replace("{provider}", provider(evt))
+        .replace("{date}", &date)
+        .to_lowercase()
+        .replace(['\\', '/', '*', '?', '"', '<', '>', '|', ',', '#', ' '], "-");
+    name.trim_start_matches(['-', '_', '+']).to_string()
+}
+
+/// The provider name of the event, or its channel
+fn provider(evt: &Event) -> &str {
+    evt.System
+        .Provider
+        .as_ref()
+        .and_then(|p| p.Name.as_deref())
+        .unwrap_or(&evt.System.Channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_splunk_hec() {
+        let events = [from_file("data/winevt1.xml").unwrap(), from_file("data/winevt5.xml").unwrap()];
+        let body = to_splunk_hec(&events, &HecOptions::default()).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(body.ends_with('\n'));
+        assert!(lines[0].starts_with(
+            r#"{"time":1611659849.485,"host":"DESKTOP-G089JUF","source":"WinEventLog:Security","sourcetype":"Microsoft-Windows-Security-Auditing","event":{"xmlns":"#
+        ), "{}", lines[0]);
+        assert!(lines[1].contains(
+            r#""source":"WinEventLog:Microsoft-Windows-Windows Defender/Operational","sourcetype":"Microsoft-Windows-Windows Defender","#
+        ), "{}", lines[1]);
+
+        let opts = HecOptions {
+            payload: Payload::Ecs,
+            sourcetype: Some("XmlWinEventLog".to_string()),
+            index: Some("wineventlog".to_string()),
+        };
+        let body = to_splunk_hec(&events[..1], &opts).unwrap();
+        let hec: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(hec["sourcetype"], "XmlWinEventLog");
+        assert_eq!(hec["index"], "wineventlog");
+        assert_eq!(hec["event"], events[0].to_ecs());
+    }
+
+    #[test]
+    fn test_elastic_bulk() {
+        let events = [from_file("data/winevt1.xml").unwrap(), from_file("data/winevt5.xml").unwrap()];
+        let body = to_elastic_bulk(&events, &BulkOptions::default()).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(body.ends_with('\n'));
+        assert_eq!(lines[0], r#"{"index":{"_index":"winevents-security-2021.01.26"}}"#);
+        assert_eq!(serde_json::from_str::<Value>(lines[1]).unwrap(), events[0].to_ecs());
+        assert_eq!(
+            lines[2],
+            r#"{"index":{"_index":"winevents-microsoft-windows-windows-defender-operational-2021.01.29"}}"#
+        );
+
+        let opts = BulkOptions {
+            payload: Payload::Json,
+            index: "{provider}-{date}".to_string(),
+            date_format: "%Y.%m".to_string(),
+            action: BulkAction::Create,
+            with_id: true,
+        };
+        let body = to_elastic_bulk(&events[..1], &opts).unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let lines = body.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            r#"{"create":{"_index":"microsoft-windows-security-auditing-2021.01","_id":"DESKTOP-G089JUF-Security-26893"}}"#
+        );
+        assert_eq!(lines[1], serde_json::to_string(&events[0]).unwrap());
+
+        let opts = BulkOptions { date_format: "%Y.%Q".to_string(), ..Default::default() };
+        let err = to_elastic_bulk(&events, &opts).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::DateFormat);
+        assert_eq!(err.to_string(), "invalid date format `%Y.%Q`");
+    }
+}
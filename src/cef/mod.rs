@@ -1,50 +1,273 @@
-use serde_json;
-
-#[macro_use]
-pub(crate) mod macros;
+pub(crate) mod options;
 pub(crate) mod parser;
 pub(crate) mod utils;
 
+pub use options::{CefOptions, RawEvent};
+
 use crate::{
     Event,
-    mappers::levels,
+    error::Result,
+    mappers::MappingRegistry,
 };
 
 pub trait ToCEF {
-    fn to_cef(&self) -> String;
+    /// Converts into CEF with the default options
+    fn to_cef(&self) -> String {
+        self.to_cef_with(&CefOptions::default())
+    }
+
+    /// Converts into CEF with the given header and severity options.
+    /// Custom fields that find no free `csN`/`cnN`/`deviceCustomDateN` slot are left out
+    fn to_cef_with(&self, opts: &CefOptions) -> String;
+
+    /// Converts into CEF with the given options, failing with
+    /// [`ErrorKind::CefOverflow`](crate::ErrorKind::CefOverflow) if the event
+    /// has more custom fields than CEF has slots for
+    fn try_to_cef_with(&self, opts: &CefOptions) -> Result<String>;
+
+    /// Converts into CEF, mapping the event data
+    /// with the given registry instead of the built-in one
+    fn to_cef_with_mappings(&self, registry: &MappingRegistry) -> String;
 }
 
 impl ToCEF for Event {
-    fn to_cef(&self) -> String {
-        //! Converts Windows Event into
-        //! Common Event Format (CEF)
-
-        // save the raw event
-        let cef_raw = serde_json::to_string(self).unwrap();
-
-        // Build the CEF header
-        let cef_header = format!(
-            "CEF:0|Microsoft|Windows|{product_ver}|{clss_id}|{name}|{sev}|",
-            product_ver = "Windows 7",
-            clss_id=parser::get_class_id(self),
-            name=self.System.Event.EventName,
-            sev=levels::from_string_to_usize(&self.System.Level)
-        );
-
-        // Build the CEF extension
-        let cef_extension = parser::build_cef_extension(&self);
-        let cef_extension = cef_extension
-            .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<String>>()
-            .join(" ");
-
-        // final cef string
-        format!(
-            "{}{} rawEvent={}",
-            cef_header.replace("\"", ""),
-            cef_extension.replace("\"", ""),
-            cef_raw
-        )
-    }
-}
\ No newline at end of file
+    fn to_cef_with(&self, opts: &CefOptions) -> String {
+        let cef_extension = parser::build_cef_extension(self, MappingRegistry::builtin()).unwrap_or_else(|o| o.extension);
+        format_cef(self, opts, cef_extension)
+    }
+
+    fn try_to_cef_with(&self, opts: &CefOptions) -> Result<String> {
+        let cef_extension = parser::build_cef_extension(self, MappingRegistry::builtin())?;
+        Ok(format_cef(self, opts, cef_extension))
+    }
+
+    fn to_cef_with_mappings(&self, registry: &MappingRegistry) -> String {
+        let cef_extension = parser::build_cef_extension(self, registry).unwrap_or_else(|o| o.extension);
+        format_cef(self, &CefOptions::default(), cef_extension)
+    }
+}
+
+fn format_cef(evt: &Event, opts: &CefOptions, mut cef_extension: parser::CefObject) -> String {
+    //! Converts Windows Event into
+    //! Common Event Format (CEF)
+    cef_extension.insert("start".to_string(), opts.time_format.format(&evt.System.TimeCreated));
+
+    // Build the CEF header
+    let cef_header = format!(
+        "CEF:0|{vendor}|{product}|{product_ver}|{clss_id}|{name}|{sev}|",
+        vendor = utils::escape_header(&opts.vendor.resolve(evt)),
+        product = utils::escape_header(&opts.product.resolve(evt)),
+        product_ver = utils::escape_header(&opts.version.resolve(evt)),
+        clss_id = utils::escape_header(&parser::get_class_id(evt)),
+        name = utils::escape_header(&evt.System.Event.EventName),
+        sev = opts.severity(&evt.System.Level)
+    );
+
+    // Build the CEF extension
+    let cef_extension = cef_extension
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, utils::escape_extension(v)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let cef = format!("{}{}", cef_header, cef_extension);
+
+    // save the raw event
+    let cef_raw = match opts.raw_event {
+        RawEvent::Omit => return cef,
+        RawEvent::Json => utils::escape_extension(&serde_json::to_string(evt).unwrap()),
+        RawEvent::Base64 => utils::escape_extension(&base64::encode(serde_json::to_string(evt).unwrap())),
+    };
+
+    // final cef string, the raw event being cut to fit the line if need be
+    let room = opts
+        .max_line_len
+        .map(|max| max.saturating_sub(cef.len() + RAW_EVENT.len()))
+        .unwrap_or(usize::MAX);
+    if cef_raw.len() <= room {
+        return format!("{}{}{}", cef, RAW_EVENT, cef_raw);
+    }
+    let room = room.saturating_sub(RAW_EVENT_TRUNCATED.len());
+    let cef_raw = match opts.raw_event {
+        // A cut base64 string has no `=` padding to escape
+        RawEvent::Base64 => &cef_raw[..room - room % 4],
+        _ => utils::truncate_escaped(&cef_raw, room),
+    };
+    if cef_raw.is_empty() {
+        format!("{}{}", cef, RAW_EVENT_TRUNCATED)
+    } else {
+        format!("{}{}{}{}", cef, RAW_EVENT_TRUNCATED, RAW_EVENT, cef_raw)
+    }
+}
+
+const RAW_EVENT: &str = " rawEvent=";
+const RAW_EVENT_TRUNCATED: &str = " rawEventTruncated=true";
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{from_file, HeaderField, Level, TimeFormat};
+
+    /// Split a CEF line into its header fields and extension, unescaping them
+    fn parse_cef(cef: &str) -> (Vec<String>, HashMap<String, String>) {
+        let mut header = Vec::new();
+        let mut field = String::new();
+        let mut chars = cef.chars();
+        while header.len() < 7 {
+            match chars.next().expect("truncated CEF header") {
+                '\\' => field.push(chars.next().unwrap()),
+                '|' => header.push(std::mem::take(&mut field)),
+                c => field.push(c),
+            }
+        }
+
+        // Each unescaped `=` ends a key, the key starting after the last space
+        let mut extension = HashMap::new();
+        let mut key: Option<String> = None;
+        let mut value = String::new();
+        let mut chars = chars.peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next().unwrap() {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    c => value.push(c),
+                },
+                '=' => {
+                    let split = value.rfind(' ').map_or(0, |i| i + 1);
+                    let next_key = value[split..].to_string();
+                    value.truncate(split.saturating_sub(1));
+                    if let Some(k) = key.replace(next_key) {
+                        extension.insert(k, std::mem::take(&mut value));
+                    }
+                    value.clear();
+                }
+                c => value.push(c),
+            }
+        }
+        extension.insert(key.unwrap(), value);
+        (header, extension)
+    }
+
+    #[test]
+    fn test_options() {
+        let e = from_file("data/winevt5.xml").unwrap();
+        let (header, _) = parse_cef(&e.to_cef());
+        assert_eq!(header[1..4], ["Microsoft", "Windows", "Windows 7"]);
+        assert_eq!(header[6], "2");
+
+        let mut opts = CefOptions {
+            vendor: HeaderField::fixed("Contoso|Corp"),
+            product: HeaderField::Computer,
+            version: HeaderField::Data { name: "Platform version".to_string(), default: "unknown".to_string() },
+            ..Default::default()
+        };
+        opts.severity.insert("Information".to_string(), 3);
+        let (header, _) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(header[1..4], ["Contoso|Corp", "DESKTOP-G089JUF", "4.18.2101.4"]);
+        assert_eq!(header[6], "3");
+
+        // Missing Data and unknown levels fall back to their defaults
+        let mut e = from_file("data/winevt1.xml").unwrap();
+        e.System.Level = Level { value: 42, name: "42".to_string() };
+        opts.default_severity = 5;
+        let (header, _) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(header[3], "unknown");
+        assert_eq!(header[6], "5");
+    }
+
+    #[test]
+    fn test_time_format() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let (_, extension) = parse_cef(&e.to_cef());
+        assert_eq!(extension["start"], "1611659849");
+
+        let opts = CefOptions { time_format: TimeFormat::EpochMillis, ..Default::default() };
+        let (_, extension) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(extension["start"], "1611659849485");
+
+        let opts = CefOptions { time_format: TimeFormat::Cef, ..Default::default() };
+        let (_, extension) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(extension["start"], "Jan 26 2021 11:17:29.485");
+    }
+
+    #[test]
+    fn test_raw_event() {
+        let e = from_file("data/winevt5.xml").unwrap();
+        let json = serde_json::to_string(&e).unwrap();
+
+        let opts = CefOptions { raw_event: RawEvent::Omit, ..Default::default() };
+        let (_, extension) = parse_cef(&e.to_cef_with(&opts));
+        assert!(!extension.contains_key("rawEvent"));
+
+        let opts = CefOptions { raw_event: RawEvent::Base64, ..Default::default() };
+        let (_, extension) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(base64::decode(&extension["rawEvent"]).unwrap(), json.as_bytes());
+        assert!(!extension.contains_key("rawEventTruncated"));
+
+        // Truncated to fit the line limit
+        let limit = e.to_cef().len() - 500;
+        for raw_event in [RawEvent::Json, RawEvent::Base64] {
+            let opts = CefOptions { raw_event, max_line_len: Some(limit), ..Default::default() };
+            let cef = e.to_cef_with(&opts);
+            assert!(cef.len() <= limit && cef.len() > limit - 4, "{}", cef.len());
+            let (_, extension) = parse_cef(&cef);
+            assert_eq!(extension["rawEventTruncated"], "true");
+            let raw = &extension["rawEvent"];
+            match raw_event {
+                RawEvent::Base64 => {
+                    let decoded = base64::decode(raw).unwrap();
+                    assert!(json.as_bytes().starts_with(&decoded));
+                }
+                _ => assert!(json.starts_with(raw.as_str())),
+            }
+        }
+
+        // Not enough room for any of the raw event
+        let opts = CefOptions { max_line_len: Some(100), ..Default::default() };
+        let (_, extension) = parse_cef(&e.to_cef_with(&opts));
+        assert_eq!(extension["rawEventTruncated"], "true");
+        assert!(!extension.contains_key("rawEvent"));
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(utils::escape_header("a|b\\c\nd"), "a\\|b\\\\c d");
+        assert_eq!(utils::escape_extension("a=b\\c|d\r\ne"), "a\\=b\\\\c|d\\ne");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        //! Every sample converts to CEF that parses back to the same header,
+        //! extension values and raw event
+        let mut samples = std::fs::read_dir("data")
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "xml"))
+            .collect::<Vec<_>>();
+        samples.sort();
+        assert!(!samples.is_empty());
+
+        for sample in samples {
+            let mut e = from_file(&sample).unwrap();
+            // Values with every character that needs escaping
+            e.System.Event.EventName = "Name | with \\ pipes = and\nbreaks".to_string();
+            e.System.Computer = "HOST\\with=equal\r\n|pipe".to_string();
+            let cef = e.to_cef();
+            assert!(!cef.contains('\n') && !cef.contains('\r'), "{}", sample.display());
+
+            let (header, extension) = parse_cef(&cef);
+            assert_eq!(header[0], "CEF:0");
+            assert_eq!(header[4], parser::get_class_id(&e));
+            assert_eq!(header[5], "Name | with \\ pipes = and breaks");
+
+            let expected = parser::build_cef_extension(&e, MappingRegistry::builtin()).unwrap();
+            for (k, v) in expected.iter() {
+                assert_eq!(extension.get(k), Some(v), "{}: `{}`", sample.display(), k);
+            }
+            let raw: serde_json::Value = serde_json::from_str(&extension["rawEvent"]).unwrap();
+            assert_eq!(raw, serde_json::to_value(&e).unwrap(), "{}", sample.display());
+        }
+    }
+}
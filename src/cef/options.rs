@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use crate::{
+    de::Level,
+    header::{self, HeaderField},
+    time::TimeFormat,
+};
+
+/// How the event is embedded in the `rawEvent` extension field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawEvent {
+    /// The event serialized to JSON
+    Json,
+    /// The event serialized to JSON, base64 encoded
+    Base64,
+    /// No `rawEvent` field
+    Omit,
+}
+
+/// Options of the CEF conversion
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, CefOptions, HeaderField, ToCEF};
+///
+/// let mut opts = CefOptions {
+///     product: HeaderField::Computer,
+///     version: HeaderField::fixed("10.0.19041"),
+///     ..Default::default()
+/// };
+/// opts.severity.insert("Information".to_string(), 1);
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let cef = e.to_cef_with(&opts);
+/// assert!(cef.starts_with("CEF:0|Microsoft|DESKTOP-G089JUF|10.0.19041|"));
+/// assert!(cef.contains("|An account was successfully logged on.|1|"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CefOptions {
+    /// Device Vendor of the header, `Microsoft` by default
+    pub vendor: HeaderField,
+    /// Device Product of the header, `Windows` by default
+    pub product: HeaderField,
+    /// Device Version of the header, `Windows 7` by default
+    pub version: HeaderField,
+    /// CEF severity (0 to 10) of the event levels, keyed by level name (eg: `Error`)
+    pub severity: HashMap<String, usize>,
+    /// Severity of the levels missing from `severity`
+    pub default_severity: usize,
+    /// How to embed the raw event, as JSON by default
+    pub raw_event: RawEvent,
+    /// Maximum length (in bytes) of the CEF line. `rawEvent` is truncated
+    /// (and flagged with `rawEventTruncated=true`) to fit, no limit by default
+    pub max_line_len: Option<usize>,
+    /// Format of the `start` time, epoch seconds by default.
+    /// CEF itself reads epoch milliseconds or `MMM dd yyyy HH:mm:ss.SSS`
+    pub time_format: TimeFormat,
+}
+
+impl Default for CefOptions {
+    fn default() -> Self {
+        Self {
+            vendor: HeaderField::fixed("Microsoft"),
+            product: HeaderField::fixed("Windows"),
+            version: HeaderField::fixed("Windows 7"),
+            severity: header::default_severities(),
+            default_severity: 0,
+            raw_event: RawEvent::Json,
+            max_line_len: None,
+            time_format: TimeFormat::EpochSeconds,
+        }
+    }
+}
+
+impl CefOptions {
+    /// Get the CEF severity of an event level
+    pub(crate) fn severity(&self, level: &Level) -> usize {
+        header::severity(&self.severity, self.default_severity, level)
+    }
+}
@@ -1,52 +1,206 @@
-use chrono::DateTime;
-
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
+use super::utils;
 use crate::{
-    de::{Event, EventData},
+    de::{Event, EventData, Keywords, XmlElement},
+    error::Error,
     mappers::{
-        cef_map::CefMap,
-        load_mapping,
         EventMappingGetters,
+        EventSource,
+        MappingRegistry,
     },
-    cef::utils::*,
+    time::TimeFormat,
 };
 
-type CefObject = HashMap<String, String>;
+pub(crate) type CefObject = HashMap<String, String>;
 
-const EMPTY_STRING: String = String::new();
+/// The numbered custom fields of a CEF extension, each paired with a `Label` key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum CustomSlot {
+    String,
+    Number,
+    Date,
+}
 
-pub(crate) fn build_cef_extension(evt: &Event) -> CefObject {
-    //! Build the CEF Extension Object
-    let start = DateTime::parse_from_rfc3339(evt.System.TimeCreated.as_str());
-    let start = if let Ok(e) = start {
-        e.timestamp().to_string()
-    } else {
-        evt.System.TimeCreated.to_string()
-    };
+impl CustomSlot {
+    const ALL: [CustomSlot; 3] = [CustomSlot::String, CustomSlot::Number, CustomSlot::Date];
+
+    /// Short key prefix and full CEF name prefix of the slots
+    fn prefixes(self) -> (&'static str, &'static str) {
+        match self {
+            CustomSlot::String => ("cs", "deviceCustomString"),
+            CustomSlot::Number => ("cn", "deviceCustomNumber"),
+            CustomSlot::Date => ("deviceCustomDate", "deviceCustomDate"),
+        }
+    }
+
+    /// Number of slots defined by the CEF spec
+    fn count(self) -> usize {
+        match self {
+            CustomSlot::String => 6,
+            CustomSlot::Number => 3,
+            CustomSlot::Date => 2,
+        }
+    }
+
+    /// Get the slot kind and number of a key (eg: `cs4`, `deviceCustomNumber6`)
+    pub(crate) fn of_key(key: &str) -> Option<(Self, usize)> {
+        Self::ALL.iter().find_map(|&slot| {
+            let (short, long) = slot.prefixes();
+            let n = key.strip_prefix(long).or_else(|| key.strip_prefix(short))?;
+            if n.is_empty() || !n.chars().all(|ch| ch.is_ascii_digit()) {
+                return None;
+            }
+            n.parse().ok().map(|n| (slot, n))
+        })
+    }
+
+    fn key(self, n: usize) -> String {
+        format!("{}{}", self.prefixes().0, n)
+    }
+
+    /// Get the number of the first free slot, which may be past the last one
+    fn next_free(self, obj: &CefObject) -> usize {
+        let key = match self {
+            CustomSlot::String => utils::get_next_cs(&utils::get_cs_keys(obj)),
+            CustomSlot::Number => utils::get_next_cn(&utils::get_cn_keys(obj)),
+            CustomSlot::Date => utils::get_next_cd(&utils::get_cd_keys(obj)),
+        };
+        key[self.prefixes().0.len()..].parse().unwrap_or(usize::MAX)
+    }
+}
+
+impl fmt::Display for CustomSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = self.prefixes().0;
+        write!(f, "{}1-{}{}", prefix, prefix, self.count())
+    }
+}
+
+/// A custom field waiting for a slot
+struct CustomField {
+    slot: CustomSlot,
+    /// Slot asked for by the event mapping, if any
+    wanted: Option<usize>,
+    label: String,
+    value: String,
+}
+
+/// The event needs more custom fields than CEF has slots for
+#[derive(Debug)]
+pub(crate) struct SlotOverflow {
+    /// The extension without the fields that did not fit
+    pub(crate) extension: CefObject,
+    /// The fields left out, as `Label (cs1-cs6)`
+    pub(crate) fields: Vec<String>,
+}
+
+impl From<SlotOverflow> for Error {
+    fn from(overflow: SlotOverflow) -> Self {
+        Error::CefOverflow { fields: overflow.fields }
+    }
+}
+
+pub(crate) fn build_cef_extension(evt: &Event, registry: &MappingRegistry) -> Result<CefObject, SlotOverflow> {
+    //! Build the CEF Extension Object, mapping the event data with `registry`.
+    //! Custom strings, numbers and dates get the slot the event mapping
+    //! asks for when it is free, else the next free one, with its `Label`
+    let start = TimeFormat::EpochSeconds.format(&evt.System.TimeCreated);
     let external_id= evt.System.Event.EventID;
     let outcome = get_event_outcome(&evt.System.Keywords);
 
-    let event_data = get_event_data(&external_id, evt.EventData.as_ref());
+    let mut event_data = get_event_data(
+        registry,
+        &evt.System.source(),
+        external_id,
+        evt.EventData.as_ref(),
+        evt.UserData.as_ref(),
+    );
+    // The rendered message, when forwarded, over the one of the mapping
+    let message = evt.RenderingInfo.as_ref().and_then(|r| r.Message.as_deref());
+    if let Some(message) = message.map(str::trim).filter(|m| !m.is_empty()) {
+        event_data.insert("msg".to_string(), message.to_string());
+    }
 
-    let result = [
+    let mut result = [
         ("start".to_string(), start),
         ("external_id".to_string(), external_id.to_string()),
         ("outcome".to_string(), outcome),
-        ("cn5Label".to_string(), "EventRecordId".to_string()),
-        ("cn5".to_string(), evt.System.EventRecordID.to_string()),
-        ("cs2Label".to_string(), "Keywords".to_string()),
-        ("cs2".to_string(), evt.System.Keywords.to_string()),
     ]
         .iter()
         .map(|(k, v)|(k.to_string(), v.to_string()))
-        .collect::<HashMap<String, String>>()
-        .into_iter()
-        .chain(event_data)
-        .filter(|(k,_v)|!k.is_empty())
         .collect::<HashMap<String, String>>();
 
-    result
+    // Set the custom fields of the mapping aside
+    let mut custom = Vec::new();
+    for (k, v) in &event_data {
+        if k.is_empty() || k.strip_suffix("Label").and_then(CustomSlot::of_key).is_some() {
+            continue;
+        }
+        match CustomSlot::of_key(k) {
+            Some((slot, n)) => custom.push(CustomField {
+                slot,
+                wanted: Some(n),
+                label: event_data.get(&format!("{}Label", k)).unwrap_or(k).to_string(),
+                value: v.to_string(),
+            }),
+            None => {
+                result.insert(k.to_string(), v.to_string());
+            }
+        }
+    }
+    custom.sort_by_key(|f| (f.slot, f.wanted));
+
+    let builtin = |slot, label: &str, value: String| CustomField {
+        slot,
+        wanted: None,
+        label: label.to_string(),
+        value,
+    };
+    custom.push(builtin(CustomSlot::Number, "EventRecordId", evt.System.EventRecordID.to_string()));
+    custom.push(builtin(CustomSlot::String, "Keywords", evt.System.Keywords.to_string()));
+    if let Some(data) = get_positional_data(evt.EventData.as_ref()) {
+        custom.push(builtin(CustomSlot::String, "EventData", data));
+    }
+
+    let fields = allocate_custom_fields(&mut result, custom);
+    if fields.is_empty() {
+        Ok(result)
+    } else {
+        Err(SlotOverflow { extension: result, fields })
+    }
+}
+
+/// Put the custom fields into their slots.
+/// Returns the fields left out for lack of a free slot
+fn allocate_custom_fields(result: &mut CefObject, fields: Vec<CustomField>) -> Vec<String> {
+    let insert = |result: &mut CefObject, field: &CustomField, n: usize| {
+        let key = field.slot.key(n);
+        result.insert(format!("{}Label", key), field.label.to_string());
+        result.insert(key, field.value.to_string());
+    };
+
+    // Mapped slots first, so that they are not taken by the relocated fields
+    let mut pending = Vec::new();
+    for field in fields {
+        match field.wanted {
+            Some(n) if n <= field.slot.count() && !result.contains_key(&field.slot.key(n)) => {
+                insert(result, &field, n)
+            }
+            _ => pending.push(field),
+        }
+    }
+
+    let mut overflow = Vec::new();
+    for field in pending {
+        let n = field.slot.next_free(result);
+        if n <= field.slot.count() {
+            insert(result, &field, n);
+        } else {
+            overflow.push(format!("{} ({})", field.label, field.slot));
+        }
+    }
+    overflow
 }
 
 pub(crate) fn get_class_id(evt: &Event) -> String {
@@ -57,185 +211,203 @@ pub(crate) fn get_class_id(evt: &Event) -> String {
         p
     } else { return evt.clone().System.Channel };
 
-    let name = if let Some(n) = provider.clone().Name {
+    if let Some(n) = provider.clone().Name {
         n
-    } else { evt.clone().System.Channel };
-
-    name
+    } else { evt.clone().System.Channel }
 }
 
-pub(crate) fn get_event_outcome(keyword: &String) -> String {
-    //! Get the Event Outcome
-    match keyword.as_str() {
-        "Audit Success" => "/Success",
-        "Audit Failure" => "/Failure",
-        _ => ""
+pub(crate) fn get_event_outcome(keywords: &Keywords) -> String {
+    //! Get the Event Outcome from the audit flags of the keywords
+    if keywords.is_audit_success() {
+        "/Success"
+    } else if keywords.is_audit_failure() {
+        "/Failure"
+    } else {
+        ""
     }
         .into()
 }
 
-/// Parse the Event data and return a CEF Object of it
-fn get_event_data(event_id: &usize, event_data: Option<&EventData>) -> CefObject {
-    let mut result: HashMap<String, String> = HashMap::new();
-
-    if let Some(data) = event_data {
-        let evt_data = &data.Data;
-        if evt_data.is_array() {
-            // Event Data is an Array
-            let arr = evt_data
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|a|a.to_string().replace("\"", ""))
-                .collect::<Vec<String>>();
-            result.insert("cs6Label".into(), "EventData".into());
-            result.insert("cs6".into(), arr.join(","));
-        } else if evt_data.is_object() {
-            let obj = evt_data.as_object().unwrap();
-            let obj = obj
-                .iter()
-                .map(|(k, v)|(k.as_str(), v.as_str().unwrap_or("")))
-                .map(|(k, v)|{
-                    (k.trim_matches('\\'), v.trim_matches('\\'))
-                })
-                .map(|(k, v)|(k.to_string(), v.to_string()))
-                .collect::<HashMap<String, String>>();
-
-            // convert to cef string
-            let parsed_cef = mapper(&event_id, &obj);
-            let parsed_cef = if let Some(cef) = parsed_cef {
-                cef
-            } else {
-                HashMap::new()
-            };
-            result = parsed_cef
-        }
+/// Map the Event data, and the UserData addressed by path
+/// (eg: `UserData/LogFileCleared/SubjectUserName`, `UserData/Root/@attribute`)
+fn get_event_data(
+    registry: &MappingRegistry,
+    source: &EventSource,
+    event_id: usize,
+    event_data: Option<&EventData>,
+    user_data: Option<&XmlElement>,
+) -> CefObject {
+    let mut obj = event_data.map(|d| d.Data.to_map()).unwrap_or_default();
+    if let Some(user_data) = user_data {
+        user_data.add_paths("UserData", &mut obj);
+    }
+    if obj.is_empty() {
+        // No Event Data found
+        return CefObject::new();
+    }
+    let obj = obj
+        .into_iter()
+        .map(|(k, v)|(k.trim_matches('\\').to_string(), v.trim_matches('\\').to_string()))
+        .collect::<HashMap<String, String>>();
+
+    // convert to cef string
+    mapper(registry, source, event_id, &obj).unwrap_or_default()
+}
 
-        // Return the formated CEF HashMap
-        result
+/// Join the unnamed (positional) Event data, if the event has any
+fn get_positional_data(event_data: Option<&EventData>) -> Option<String> {
+    let arr = event_data?
+        .Data
+        .unnamed()
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    if arr.is_empty() {
+        None
     } else {
-        // No Event Data found
-        result
+        Some(arr.join(","))
     }
 }
 
 /// Map & convert the Events into a CEF HashMap
-fn mapper(event_id: &usize, event_data: &HashMap<String, String>) -> Option<CefObject> {
-    let evt_mapping = load_mapping();
-    let map = if let Some(m) = evt_mapping.get_mapping_info(&event_id) {
-        // Found mapping information
-        m
-    }else {
-        // We dont have a mapping, so return new()
-        return None
-    };
+fn mapper(
+    registry: &MappingRegistry,
+    source: &EventSource,
+    event_id: usize,
+    event_data: &HashMap<String, String>,
+) -> Option<CefObject> {
+    let map = registry.events.get_mapping_info(source, event_id)?;
 
-    // Load the CEF Mapping fields
-    let cef_map = CefMap::load_cef_map();
+    // The CEF Mapping fields
+    let cef_map = &registry.cef;
     // Build the CEF HashMap Object
-    let result = map
-        .iter()
-        .map(|(k, v)|{
-            let xml_key = v;
-            if k.eq_ignore_ascii_case("message") {
-                // We have a message
-                if xml_key.starts_with("concatenate") {
-                    // We have a message that needs to be concatenated
-                    format!("msg={}", do_msg_concat(xml_key, &event_data))
-                } else if xml_key.eq_ignore_ascii_case("all_of_data") {
-                    // All_Of_Data found for message
-                    format!("msg={}", do_all_of_data(&event_data))
-                } else {
-                    // Normal message
-                    format!("msg={}", xml_key)
-                }
-            } else if k.eq_ignore_ascii_case("device action"){
-                // Got a Device Action Message
-                if xml_key.matches(" ").count() > 0 {
-                    format!("act={}", xml_key)
-                } else {
-                    format!("act={}", event_data.get(xml_key).unwrap_or(&EMPTY_STRING).to_string())
-                }
-            } else {
-                // Mapping if not a message field
-                let cef_key = cef_map.get_cef_field_or_default(&k);
-                let cef_val = if xml_key.starts_with("one_of(") {
-                    // One_Of(..) value encountered
-                    do_one_of(xml_key, &event_data)
-                } else if xml_key.starts_with("all_of(") {
-                    // One_Of(..) value encountered
-                    do_all_of(xml_key, &event_data)
-                } else if xml_key.starts_with("both(") {
-                    // Both(..) value encountered
-                    xml_key
-                        .split(",")
-                        .map(|x| x.replace("both (", "").replace(")", ""))
-                        .map(|x| {
-                            event_data.get(&x).unwrap_or(&EMPTY_STRING).to_string()
-                        })
-                        .collect::<Vec<String>>()
-                        .join(" ")
-                } else if xml_key.starts_with("concatenate") {
-                    // Concatenate(..) values encountered
-                    String::new()
-                    // TODO: need to do the concat logic
-                } else if xml_key.contains("IPv6 Address)") {
-                    // IPv6 address mostly available
-                    let xml_fields = xml_key
-                        .splitn(2, " ")
-                        .map(|x| x.to_string())
-                        .collect::<Vec<String>>()
-                        .iter()
-                        .nth(0)
-                        .unwrap_or(&EMPTY_STRING)
-                        .to_string();
-                    let addr = if let Some(a) = event_data.get(&xml_fields) {
-                        if a.contains(":") { a.to_string() } else { EMPTY_STRING }
-                    } else {
-                        EMPTY_STRING
-                    };
-                    addr
-                } else {
-                    // Common mapping
-                    if xml_key.starts_with("%") && xml_key.matches("%").count() == 1 {
-                        // Mapping that correlates to a parameter
-                        let param = "param".to_string() + xml_key.replace("%", "").as_str();
-                        event_data.get(&param).unwrap_or(&EMPTY_STRING).to_string()
-                    } else {
-                        // Normal mapping
-                        event_data.get(xml_key).unwrap_or(&EMPTY_STRING).to_string()
-                    }
-                };
-                // Add the device custom labels if device custom values are present
-                let label = if k.contains(" Custom ") {
-                    format!("{}Label={}", cef_key, xml_key).replace("\"", "")
-                } else { EMPTY_STRING };
-                // build the k=v cef string
-                format!("{:?}={:?}~{}", cef_key.trim(), cef_val.trim(), label.trim()).trim().to_string()
+    let mut result = CefObject::new();
+    for (k, mapping) in map {
+        let value = mapping.eval(event_data).trim().to_string();
+        if k.eq_ignore_ascii_case("message") {
+            result.insert("msg".to_string(), value);
+        } else if k.eq_ignore_ascii_case("device action") {
+            result.insert("act".to_string(), value);
+        } else {
+            let cef_key = cef_map.get_cef_field_or_default(k).trim().to_string();
+            if cef_key.is_empty() {
+                continue;
             }
-        })
-        .map(|x|{
-            x
-                .split("~")
-                .filter(|x|!x.is_empty())
-                .map(|x|x.to_string())
-                .collect::<Vec<_>>()
-                .iter()
-                .map(|x|{
-                    let components = x
-                        .split("=")
-                        .filter(|x|!x.is_empty())
-                        .map(|x|x.to_string())
-                        .collect::<Vec<String>>();
-                    (
-                        components[0].replace("\"", ""),
-                        components[1].replace("\"", "").replace("\\\\", "\\")
-                    )
-                })
-                .collect::<CefObject>()
-        })
-        .flatten()
-        .collect::<CefObject>();
+            // Add the device custom labels if device custom values are present
+            if k.contains(" Custom ") {
+                result.insert(format!("{}Label", cef_key), mapping.label());
+            }
+            result.insert(cef_key, value);
+        }
+    }
 
     Some(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_concatenate() {
+        let e = from_file("data/winevt7.xml").unwrap();
+        let cef = build_cef_extension(&e, MappingRegistry::builtin()).unwrap();
+        assert_eq!(
+            cef["msg"],
+            "The process C:\\Windows\\System32\\RuntimeBroker.exe (DESKTOP-LAJA961) has initiated the \
+             power off of computer DESKTOP-LAJA961 on behalf of user DESKTOP-LAJA961\\alphasun \
+             for the following reason: Other (Unplanned)"
+        );
+
+        // Any mapped field can be concatenated
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv(
+                r#"4624,,,,,,"Destination User Name:concatenate(TargetDomainName,"" \ "",TargetUserName)""#,
+            )
+            .unwrap()
+            .build();
+        let e = from_file("data/winevt1.xml").unwrap();
+        let cef = build_cef_extension(&e, &registry).unwrap();
+        assert_eq!(cef["duser"], "NT AUTHORITY \\ SYSTEM");
+    }
+
+    #[test]
+    fn test_custom_slots() {
+        // Mapped slots are kept, the built-in fields take the free ones
+        let e = from_file("data/winevt7.xml").unwrap();
+        let cef = build_cef_extension(&e, MappingRegistry::builtin()).unwrap();
+        assert_eq!(cef["cs4Label"], "Reason Code");
+        assert_eq!(cef["cs5Label"], "Shutdown Type");
+        assert_eq!(cef["cn1Label"], "EventRecordId");
+        assert_eq!(cef["cn1"], e.System.EventRecordID.to_string());
+        let keywords = cef.iter().find(|(_, v)| *v == "Keywords").unwrap().0;
+        let keywords = keywords.strip_suffix("Label").unwrap();
+        assert_eq!(cef[keywords], e.System.Keywords.to_string());
+        for (k, _) in cef.iter().filter(|(k, _)| CustomSlot::of_key(k).is_some()) {
+            assert!(cef.contains_key(&format!("{}Label", k)), "{} has no label", k);
+        }
+        assert!(!cef.contains_key("cn5"));
+
+        // Slots past the last one are moved to a free one
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv(
+                "4624,,,,,,Device Custom Number 1:LogonType,Device Custom Number 6:TargetLogonId",
+            )
+            .unwrap()
+            .build();
+        let e = from_file("data/winevt1.xml").unwrap();
+        let cef = build_cef_extension(&e, &registry).unwrap();
+        assert_eq!(cef["cn1"], "5");
+        assert_eq!(cef["cn2Label"], "TargetLogonId");
+        assert_eq!(cef["cn2"], "0x3e7");
+        assert_eq!(cef["cn3Label"], "EventRecordId");
+        assert!(!cef.contains_key("cn6") && !cef.contains_key("deviceCustomNumber6"));
+
+        // And reported when there is none left
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv(
+                "4624,,,,,,Device Custom Number 1:LogonType,Device Custom Number 2:TargetLogonId,\
+                 Device Custom Number 3:ProcessId",
+            )
+            .unwrap()
+            .build();
+        let overflow = build_cef_extension(&e, &registry).unwrap_err();
+        assert_eq!(overflow.fields, ["EventRecordId (cn1-cn3)"]);
+        assert_eq!(overflow.extension["cn3"], "0x250");
+        assert_eq!(
+            Error::from(overflow).to_string(),
+            "no CEF custom field slot left for EventRecordId (cn1-cn3)"
+        );
+    }
+
+    #[test]
+    fn test_rendered_message() {
+        let e = from_file("data/winevt10.xml").unwrap();
+        let cef = build_cef_extension(&e, MappingRegistry::builtin()).unwrap();
+        assert!(cef["msg"].starts_with("An account failed to log on.\n\nAccount For Which Logon Failed:"));
+        assert!(cef["msg"].ends_with("Unknown user name or bad password."));
+        assert_eq!(cef["duser"], "administrator");
+
+        // The mapping message otherwise
+        let mut e = e;
+        e.RenderingInfo = None;
+        let cef = build_cef_extension(&e, MappingRegistry::builtin()).unwrap();
+        assert!(!cef["msg"].starts_with("An account failed to log on."));
+    }
+
+    #[test]
+    fn test_user_data_paths() {
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv(
+                "2,,,,,,Destination User Name:UserData/CbsPackageChangeState/Client,\
+                 Device Custom String 1:UserData/CbsPackageChangeState/PackageIdentifier",
+            )
+            .unwrap()
+            .build();
+        let e = from_file("data/winevt4.xml").unwrap();
+        let cef = build_cef_extension(&e, &registry).unwrap();
+        assert_eq!(cef["duser"], "WindowsUpdateAgent");
+        assert_eq!(cef["cs1"], "KB4586876");
+        assert_eq!(cef["cs1Label"], "PackageIdentifier");
+    }
+}
@@ -1,14 +1,45 @@
-#![allow(dead_code)]
 use std::collections::HashMap;
 
-const EMPTY_STRING: String = String::new();
+/// Escape a CEF header field: backslashes and pipes are escaped,
+/// line breaks (not allowed in the header) are turned into spaces
+pub(crate) fn escape_header(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", " ")
+        .replace(['\r', '\n'], " ")
+}
+
+/// Escape a CEF extension value: backslashes, equal signs and line breaks
+pub(crate) fn escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Cut an escaped extension value to at most `len` bytes,
+/// without splitting a character or an escape sequence
+pub(crate) fn truncate_escaped(value: &str, len: usize) -> &str {
+    let mut end = len.min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    let trailing_backslashes = value[..end].chars().rev().take_while(|c| *c == '\\').count();
+    if trailing_backslashes % 2 == 1 {
+        end -= 1;
+    }
+    &value[..end]
+}
 
 /// Get the first int from a string
 fn parse_int(input: &str) -> Option<usize> {
     input
         .chars()
-        .skip_while(|ch| !ch.is_digit(10))
-        .take_while(|ch| ch.is_digit(10))
+        .skip_while(|ch| !ch.is_ascii_digit())
+        .take_while(|ch| ch.is_ascii_digit())
         .fold(None, |acc, ch| {
             ch.to_digit(10).map(|b| acc.unwrap_or(0) * 10 + b as usize)
         })
@@ -17,17 +48,18 @@ fn parse_int(input: &str) -> Option<usize> {
 /// Get the next usable/missing number
 fn next_usable_number(arr: Vec<usize>) -> usize {
     let mut arr = arr;
-    arr.sort();
+    arr.sort_unstable();
+    arr.dedup();
     for (i, num) in arr.iter().enumerate() {
         if i+1 != *num{
             return i+1;
         }
     }
-    1
+    arr.len() + 1
 }
 
 /// Get the next CNn
-pub(crate) fn get_next_cn(cn: &Vec<String>) -> String {
+pub(crate) fn get_next_cn(cn: &[String]) -> String {
     let available_cn_num = cn
         .iter()
         .map(|x|parse_int(x).unwrap_or(0))
@@ -37,7 +69,7 @@ pub(crate) fn get_next_cn(cn: &Vec<String>) -> String {
 }
 
 /// Get the next CSn
-pub(crate) fn get_next_cs(cs: &Vec<String>) -> String {
+pub(crate) fn get_next_cs(cs: &[String]) -> String {
     let available_cs_num = cs
         .iter()
         .map(|x|parse_int(x).unwrap_or(0))
@@ -46,93 +78,39 @@ pub(crate) fn get_next_cs(cs: &Vec<String>) -> String {
     format!("cs{}", next_cs_num)
 }
 
-
-/// Lookup into the Values of the given XML Keys and return one of the Values
-pub(crate) fn do_one_of(xml_key: &String, event_data: &HashMap<String, String>) -> String {
-    let xml_fields = xml_key
-        .split(",")
-        .map(|x| x.replace("one_of(", "").replace(")", ""))
-        .map(|x| {
-            // Get the XML Value(s) from the XML Key(s)
-            if x == "localhost" {
-                "localhost".to_string()
-            } else if x == "No" {
-                "No".to_string()
-            } else if x == "Blocked" {
-                "Blocked".to_string()
-            } else {
-                event_data.get(&x).unwrap_or(&EMPTY_STRING).to_string()
-            }
-        })
-        .collect::<Vec<String>>()
-        .join(",");
-    one_of!(xml_fields)
-}
-
-/// Lookup into the Values of the given XML Keys and return all of the Values
-pub(crate) fn do_all_of(xml_key: &String, event_data: &HashMap<String, String>) -> String {
-    let xml_fields = xml_key
-        .split(",")
-        .map(|x| x.replace("one_of(", "").replace(")", ""))
-        .map(|x| {
-            // Get the XML Value(s) from the XML Key(s)
-            event_data.get(&x).unwrap_or(&EMPTY_STRING).to_string()
-        })
-        .collect::<Vec<String>>()
-        .join(",");
-    all_of!(xml_fields)
-}
-
-/// Concat all Data params into K:V String
-pub(crate) fn do_all_of_data(event_data: &HashMap<String, String>) -> String {
-    event_data
+/// Get the next deviceCustomDaten
+pub(crate) fn get_next_cd(cd: &[String]) -> String {
+    let available_cd_num = cd
         .iter()
-        .filter(|(_k, v)| !v.is_empty())
-        .map(|(k, v)|{
-            format!("{}: {}", k, v)
-        })
-        .collect::<Vec<String>>()
-        .join("\n")
-        .replace("\"", "")
-}
-
-/// Lookup into the Values of the given XML Keys and return a concat message
-pub(crate) fn do_msg_concat(xml_key: &String, event_data: &HashMap<String, String>) -> String {
-    let msg = xml_key
-        .split(",")
-        .map(|x|{
-            let x = x
-                .replace("concatenate(", "")
-                .replace(")", "");
-            if x.starts_with("%") {
-                let param = "param".to_string() + x.replace("%", "").as_str();
-                event_data.get(&param).unwrap_or(&EMPTY_STRING).trim().to_string()
-            } else {
-                x
-            }
-        })
-        .collect::<Vec<_>>()
-        .join("")
-        .replace("\"", "");
-    msg
+        .map(|x|parse_int(x).unwrap_or(0))
+        .collect::<Vec<_>>();
+    let next_cd_num = next_usable_number(available_cd_num);
+    format!("deviceCustomDate{}", next_cd_num)
 }
 
 /// Get all CNn Keys from the given object
 pub(crate) fn get_cn_keys(obj: &HashMap<String, String>) -> Vec<String> {
-    get_keys(&obj, "cn")
+    get_keys(obj, "cn")
 }
 
 /// Get all CSn Keys from the given object
 pub(crate) fn get_cs_keys(obj: &HashMap<String, String>) -> Vec<String> {
-    get_keys(&obj, "cs")
+    get_keys(obj, "cs")
+}
+
+/// Get all deviceCustomDaten Keys from the given object
+pub(crate) fn get_cd_keys(obj: &HashMap<String, String>) -> Vec<String> {
+    get_keys(obj, "deviceCustomDate")
 }
 
-/// Get Keys with a given filter
+/// Get the numbered keys (eg: `cs1`, not `cs1Label` nor `cnt`) with a given prefix
 fn get_keys(obj: &HashMap<String, String>, filter_by: &str) -> Vec<String> {
     obj
         .keys()
-        .filter(|k|k.starts_with(filter_by))
-        .filter(|k|!k.ends_with("Label"))
+        .filter(|k|{
+            k.strip_prefix(filter_by)
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|ch| ch.is_ascii_digit()))
+        })
         .map(|k|k.to_string())
         .collect::<Vec<_>>()
-}
\ No newline at end of file
+}
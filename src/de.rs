@@ -1,19 +1,25 @@
 #![allow(non_snake_case)]
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use serde::{Deserialize, Deserializer, Serialize};
-use serde_json::{json, Value};
+use chrono::{DateTime, Utc};
+use serde::{
+    de::{Error, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{json, Map, Value};
 use serde_with::skip_serializing_none;
 
 use crate::{
     mappers::{
         event_name::eventid_map,
-        keywords::keywords_map,
+        keywords::{keyword_names, keywords_map},
         levels::level_map,
         opcode::opcode_map,
         tasks::tasks_map,
-    }
+        EventSource,
+    },
+    time,
 };
 
 #[skip_serializing_none]
@@ -22,7 +28,33 @@ pub struct Event {
     pub xmlns: String,
     pub System: System,
     pub EventData: Option<EventData>,
-    pub UserData: Option<HashMap<String, HashMap<String, String>>>,
+    #[serde(default, deserialize_with = "user_data")]
+    pub UserData: Option<XmlElement>,
+    pub RenderingInfo: Option<RenderingInfo>,
+}
+
+impl Event {
+    /// Let the localized strings of the `RenderingInfo`, if any, take precedence
+    /// over the names looked up for the level, task, opcode and keywords
+    pub(crate) fn apply_rendering_info(mut self) -> Self {
+        if let Some(info) = &self.RenderingInfo {
+            let system = &mut self.System;
+            let rendered = [
+                (&mut system.Level.name, &info.Level),
+                (&mut system.Task.name, &info.Task),
+                (&mut system.Opcode.name, &info.Opcode),
+            ];
+            for (name, text) in rendered {
+                if let Some(text) = text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+                    *name = text.to_string();
+                }
+            }
+            if !info.Keywords.is_empty() {
+                system.Keywords.names = info.Keywords.clone();
+            }
+        }
+        self
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -34,15 +66,15 @@ pub struct System {
     #[serde(alias = "EventID", deserialize_with = "eventid_map")]
     pub Event: EventInfo,
     #[serde(deserialize_with = "level_map")]
-    pub Level: String,
+    pub Level: Level,
     #[serde(deserialize_with = "tasks_map")]
-    pub Task: String,
+    pub Task: Task,
     #[serde(deserialize_with = "opcode_map")]
-    pub Opcode: String,
+    pub Opcode: Opcode,
     #[serde(deserialize_with = "keywords_map")]
-    pub Keywords: String,
-    #[serde(deserialize_with = "flatten_time_created")]
-    pub TimeCreated: String,
+    pub Keywords: Keywords,
+    #[serde(deserialize_with = "flatten_time_created", serialize_with = "time::serialize")]
+    pub TimeCreated: DateTime<Utc>,
     pub Correlation: Correlation,
     pub Execution: Execution,
     pub Channel: String,
@@ -51,6 +83,119 @@ pub struct System {
     pub Version: usize,
 }
 
+impl System {
+    /// Where the event comes from, to look up its mappings
+    pub(crate) fn source(&self) -> EventSource<'_> {
+        let provider = self.Provider.as_ref();
+        EventSource {
+            provider: provider.and_then(|p| p.Name.as_deref()),
+            guid: provider.and_then(|p| p.Guid.as_deref()),
+            channel: Some(self.Channel.as_str()),
+        }
+    }
+}
+
+/// The level of an event and its name (eg: `Information` for `4`)
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Level {
+    pub value: u8,
+    pub name: String,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// The task of an event and its name (eg: `Logon` for `12544`)
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Task {
+    pub value: u16,
+    pub name: String,
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// The opcode of an event and its name (eg: `Info` for `0`)
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Opcode {
+    pub value: u8,
+    pub name: String,
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// The keywords of an event: its 64-bit mask and the names of the flags set.
+/// Displays as the names, comma separated (or the mask if none has a name)
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Keywords {
+    /// The mask, as `0x8020000000000000`
+    pub value: u64,
+    /// Names of the standard flags (eg: `Audit Success`), or the localized ones
+    /// of the `RenderingInfo`, then of the provider flags as their hex mask (eg: `0x10`).
+    /// Channel bits (56 to 63) have none
+    pub names: Vec<String>,
+}
+
+impl Keywords {
+    pub const AUDIT_FAILURE: u64 = 0x0010_0000_0000_0000;
+    pub const AUDIT_SUCCESS: u64 = 0x0020_0000_0000_0000;
+
+    /// The keywords of a mask, with the names of its flags
+    pub fn from_value(value: u64) -> Self {
+        Self { value, names: keyword_names(value) }
+    }
+
+    /// Whether all the flags of `mask` are set
+    pub fn contains(&self, mask: u64) -> bool {
+        self.value & mask == mask
+    }
+
+    pub fn is_audit_success(&self) -> bool {
+        self.contains(Self::AUDIT_SUCCESS)
+    }
+
+    pub fn is_audit_failure(&self) -> bool {
+        self.contains(Self::AUDIT_FAILURE)
+    }
+}
+
+impl fmt::Display for Keywords {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.names.is_empty() {
+            write!(f, "0x{:016x}", self.value)
+        } else {
+            f.write_str(&self.names.join(", "))
+        }
+    }
+}
+
+/// Localized strings of the event, as forwarded by Windows Event Forwarding
+/// or exported with `wevtutil /f:RenderedXml`
+#[skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RenderingInfo {
+    pub Culture: Option<String>,
+    pub Message: Option<String>,
+    pub Level: Option<String>,
+    pub Task: Option<String>,
+    pub Opcode: Option<String>,
+    pub Channel: Option<String>,
+    pub Provider: Option<String>,
+    /// The `<Keyword>` of `<Keywords>`
+    #[serde(default, deserialize_with = "keyword_list", skip_serializing_if = "Vec::is_empty")]
+    pub Keywords: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Provider {
     pub Name: Option<String>,
@@ -85,9 +230,10 @@ pub struct Security {
     pub UserID: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A `<Data>` element as read from the XML
+#[derive(Deserialize)]
 #[serde(untagged)]
-pub enum Data {
+enum RawData {
     KV {
         Name: String,
         #[serde(default, rename = "$value")]
@@ -99,58 +245,428 @@ pub enum Data {
     },
 }
 
+/// One `<Data>` of the event data
+#[skip_serializing_none]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct DataItem {
+    /// `Name` attribute, absent for positional values
+    pub Name: Option<String>,
+    /// Text of the element, empty for `<Data Name="Unused"/>`
+    pub Value: String,
+}
+
+/// The `<Data>` elements of the event data, in document order.
+/// Duplicate names are all kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DataList(Vec<DataItem>);
+
+impl DataList {
+    /// Get the value of the first `<Data>` with the given `Name`.
+    /// `Some("")` for an empty element, `None` if there is no such element
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|d| d.Name.as_deref() == Some(name))
+            .map(|d| d.Value.as_str())
+    }
+
+    /// Get the values of all the `<Data>` with the given `Name`
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |d| d.Name.as_deref() == Some(name))
+            .map(|d| d.Value.as_str())
+    }
+
+    /// Get the value of the `n`th `<Data>` (1-based, as `%1` in messages), named or not
+    pub fn positional(&self, n: usize) -> Option<&str> {
+        n.checked_sub(1)
+            .and_then(|i| self.0.get(i))
+            .map(|d| d.Value.as_str())
+    }
+
+    /// Iterate over the `<Data>` in document order
+    pub fn iter(&self) -> std::slice::Iter<'_, DataItem> {
+        self.0.iter()
+    }
+
+    /// Iterate over the names and values of the named `<Data>`
+    pub fn named(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .filter_map(|d| d.Name.as_deref().map(|n| (n, d.Value.as_str())))
+    }
+
+    /// Iterate over the values of the `<Data>` without name
+    pub fn unnamed(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|d| d.Name.is_none())
+            .map(|d| d.Value.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The named values, plus every value as `param1`, `param2`, ...
+    /// (unless a value has that name) for the `%N` of the event mappings.
+    /// The first of duplicate names wins
+    pub(crate) fn to_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for (name, value) in self.named() {
+            map.entry(name.to_string()).or_insert_with(|| value.to_string());
+        }
+        for (i, d) in self.0.iter().enumerate() {
+            map.entry(format!("param{}", i + 1)).or_insert_with(|| d.Value.to_string());
+        }
+        map
+    }
+
+    /// The data as a JSON object in document order: named values under their
+    /// name (the first of duplicates), unnamed ones as `param1`, `param2`, ...
+    pub(crate) fn to_object(&self) -> Map<String, Value> {
+        let mut object = Map::new();
+        let mut unnamed = 0;
+        for d in &self.0 {
+            let name = match &d.Name {
+                Some(name) => name.to_string(),
+                None => {
+                    unnamed += 1;
+                    format!("param{}", unnamed)
+                }
+            };
+            object.entry(name).or_insert_with(|| Value::String(d.Value.to_string()));
+        }
+        object
+    }
+
+    /// The data in its JSON shape, the one of older versions: an object of
+    /// the named values (empty ones being `null`), an array of the non empty
+    /// unnamed ones, or both in an array. Names are kept in document order,
+    /// the last of duplicates giving the value
+    fn legacy(&self) -> Value {
+        let m = self
+            .named()
+            .map(|(n, v)| (n.to_string(), json!(Some(v).filter(|v| !v.is_empty()))))
+            .collect::<Map<_, _>>();
+        let v = self.unnamed().filter(|v| !v.is_empty()).collect::<Vec<_>>();
+
+        match (m.is_empty(), v.is_empty()) {
+            (true, true) => Default::default(),
+            (true, false) => json!(v),
+            (false, true) => json!(m),
+            (false, false) => json!([m, v]),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a DataList {
+    type Item = &'a DataItem;
+    type IntoIter = std::slice::Iter<'a, DataItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Serialize for DataList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        //! Duplicate names and empty unnamed values are lost in this shape:
+        //! `JsonOptions::event_data_items` writes all the `<Data>` instead
+        self.legacy().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DataList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Option::<Vec<RawData>>::deserialize(deserializer).map(|o| {
+            let items = o
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| match d {
+                    RawData::KV { Name, Value } => DataItem { Name: Some(Name), Value: Value.unwrap_or_default() },
+                    RawData::V { Value } => DataItem { Name: None, Value: Value.unwrap_or_default() },
+                })
+                .collect();
+            DataList(items)
+        })
+    }
+}
 
 #[skip_serializing_none]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EventData {
-    #[serde(default, deserialize_with = "eventdata_map")]
-    pub Data: Value,
+    #[serde(default)]
+    pub Data: DataList,
+    /// `<ComplexData>` elements, in document order
+    #[serde(default, deserialize_with = "complex_data", skip_serializing_if = "Vec::is_empty")]
+    pub ComplexData: Vec<XmlElement>,
     pub Binary: Option<String>,
 }
 
-fn eventdata_map<'de, D>(deserializer: D) -> Result<Value, D::Error>
-    where
-        D: Deserializer<'de>,
-{
-    Option::<Vec<Data>>::deserialize(deserializer).map(|o| {
-        if let Some(evd) = o {
-            let mut m = HashMap::new();
-            let mut v = Vec::new();
-
-            for d in evd {
-                match d {
-                    Data::KV { Name, Value } => {
-                        m.insert(Name, Value);
-                    }
-                    Data::V { Value } => {
-                        v.push(Value);
+/// An XML element of the `UserData` (or a `ComplexData` of the event data),
+/// with its attributes and children in document order.
+///
+/// It serializes to a JSON projection: attributes as `@name` keys
+/// (the namespace as `@xmlns` where it is declared), children under their name
+/// (in an array if repeated), text as `#text` or as a plain string for
+/// an element having neither attributes nor children.
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::from_file;
+///
+/// let e = from_file("data/winevt4.xml").unwrap();
+/// let user_data = e.UserData.as_ref().unwrap();
+/// assert_eq!(user_data.value("CbsPackageChangeState/PackageIdentifier"), Some("KB4586876"));
+/// assert_eq!(
+///     user_data.find("CbsPackageChangeState").unwrap().namespace.as_deref(),
+///     Some("http://manifests.microsoft.com/win/2004/08/windows/setup_provider")
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmlElement {
+    /// Tag name, prefix included
+    pub name: String,
+    /// Namespace: of the `xmlns` attribute, else the one of the parent
+    pub namespace: Option<String>,
+    /// Attributes, `xmlns` put aside
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlElement>,
+    /// Text content, `None` if the element has none
+    pub text: Option<String>,
+}
+
+impl XmlElement {
+    /// Get the value of an attribute
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get the first child with the given name
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Iterate over the children with the given name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// Get a descendant by its path of `/` separated names,
+    /// following the first of repeated children
+    pub fn find(&self, path: &str) -> Option<&XmlElement> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    /// Get the text of a descendant by its path (see `find`),
+    /// or an attribute if the path ends with `@name`.
+    /// `Some("")` for an empty element
+    pub fn value(&self, path: &str) -> Option<&str> {
+        match path.rsplit_once('/') {
+            Some((parent, attribute)) if attribute.starts_with('@') => {
+                self.find(parent)?.attribute(&attribute[1..])
+            }
+            None if path.starts_with('@') => self.attribute(&path[1..]),
+            _ => self.find(path).map(|e| e.text.as_deref().unwrap_or_default()),
+        }
+    }
+
+    /// Add the text and attributes of this element and of its descendants to `map`,
+    /// keyed by their path from `path` (`path/Child`, `path/Child/@name`).
+    /// The first of repeated children wins
+    pub(crate) fn add_paths(&self, path: &str, map: &mut HashMap<String, String>) {
+        if self.text.is_some() || self.children.is_empty() {
+            map.entry(path.to_string())
+                .or_insert_with(|| self.text.clone().unwrap_or_default());
+        }
+        for (name, value) in &self.attributes {
+            map.entry(format!("{}/@{}", path, name)).or_insert_with(|| value.to_string());
+        }
+        for child in &self.children {
+            child.add_paths(&format!("{}/{}", path, child.name), map);
+        }
+    }
+
+    /// The JSON projection of the element, declaring its namespace
+    /// if it is not the one of its parent
+    fn project(&self, parent_namespace: Option<&str>) -> Value {
+        let namespace = self.namespace.as_deref().filter(|ns| Some(*ns) != parent_namespace);
+        if namespace.is_none() && self.attributes.is_empty() && self.children.is_empty() {
+            return Value::String(self.text.clone().unwrap_or_default());
+        }
+
+        let mut object = Map::new();
+        if let Some(namespace) = namespace {
+            object.insert("@xmlns".to_string(), namespace.into());
+        }
+        for (name, value) in &self.attributes {
+            object.insert(format!("@{}", name), value.as_str().into());
+        }
+        for child in &self.children {
+            if object.contains_key(&child.name) {
+                continue;
+            }
+            let mut values = self
+                .children_named(&child.name)
+                .map(|c| c.project(self.namespace.as_deref()))
+                .collect::<Vec<_>>();
+            let value = if values.len() == 1 { values.remove(0) } else { Value::Array(values) };
+            object.insert(child.name.to_string(), value);
+        }
+        if let Some(text) = &self.text {
+            object.insert("#text".to_string(), text.as_str().into());
+        }
+        Value::Object(object)
+    }
+
+    /// Set the namespace of the elements without one to the one of their parent
+    fn inherit_namespace(&mut self, parent_namespace: Option<&str>) {
+        if self.namespace.is_none() {
+            self.namespace = parent_namespace.map(str::to_string);
+        }
+        for child in &mut self.children {
+            child.inherit_namespace(self.namespace.as_deref());
+        }
+    }
+}
+
+impl Serialize for XmlElement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.project(None).serialize(serializer)
+    }
+}
+
+/// A node as read from the XML: an attribute (or text) value, or an element.
+/// The name of an element is the key it is read under
+enum XmlNode {
+    Text(String),
+    Element(XmlElement),
+}
+
+impl XmlNode {
+    fn into_element(self, name: &str) -> XmlElement {
+        let mut element = match self {
+            XmlNode::Text(text) => XmlElement { text: Some(text), ..Default::default() },
+            XmlNode::Element(element) => element,
+        };
+        element.name = name.to_string();
+        element
+    }
+}
+
+impl<'de> Deserialize<'de> for XmlNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = XmlNode;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an XML element")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<XmlNode, E> {
+                Ok(XmlNode::Text(v.to_string()))
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<XmlNode, E> {
+                Ok(XmlNode::Text(v))
+            }
+
+            fn visit_unit<E>(self) -> Result<XmlNode, E> {
+                Ok(XmlNode::Element(XmlElement::default()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<XmlNode, A::Error> {
+                // Attributes come as text, child elements as maps, the text as `$value`
+                let mut element = XmlElement::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match map.next_value::<XmlNode>()? {
+                        XmlNode::Text(text) if key == "$value" => match &mut element.text {
+                            Some(t) => t.push_str(&text),
+                            None => element.text = Some(text),
+                        },
+                        XmlNode::Text(ns) if key == "xmlns" => element.namespace = Some(ns),
+                        XmlNode::Text(value) => element.attributes.push((key, value)),
+                        node => element.children.push(node.into_element(&key)),
                     }
                 }
+                Ok(XmlNode::Element(element))
             }
-            // remove null from vec
-            let v = v
-                .iter()
-                .filter(|a|!a.as_ref().is_none())
-                .map(|a|a.as_ref().unwrap().to_string())
-                .collect::<Vec<String>>();
-
-            match (m.is_empty(), v.is_empty()) {
-                (true, true) => Default::default(),
-                (true, false) => json!(v),
-                (false, true) => json!(m),
-                (false, false) => json!([m, v]),
-            }
-        } else {
-            Default::default()
         }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+fn user_data<'de, D>(deserializer: D) -> Result<Option<XmlElement>, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    Option::<XmlNode>::deserialize(deserializer).map(|node| {
+        node.map(|node| {
+            let mut element = node.into_element("UserData");
+            element.inherit_namespace(None);
+            element
+        })
+    })
+}
+
+fn complex_data<'de, D>(deserializer: D) -> Result<Vec<XmlElement>, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    Vec::<XmlNode>::deserialize(deserializer).map(|nodes| {
+        nodes
+            .into_iter()
+            .map(|node| {
+                let mut element = node.into_element("ComplexData");
+                element.inherit_namespace(None);
+                element
+            })
+            .collect()
+    })
+}
+
+fn keyword_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Keywords {
+        #[serde(default)]
+        Keyword: Vec<String>,
+    }
+
+    Option::<Keywords>::deserialize(deserializer).map(|k| {
+        k.map(|k| k.Keyword)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty())
+            .collect()
     })
 }
 
-fn flatten_time_created<'de, D>(deserializer: D) -> Result<String, D::Error>
+fn flatten_time_created<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
     where
         D: Deserializer<'de>,
 {
-    TimeCreated::deserialize(deserializer).map(|x| x.SystemTime)
+    let time = TimeCreated::deserialize(deserializer)?.SystemTime;
+    DateTime::parse_from_rfc3339(time.trim())
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| D::Error::custom(format!("invalid timestamp `{}`: {}", time.trim(), e)))
 }
 
 fn default_device_vendor() -> String {
@@ -0,0 +1,190 @@
+use std::net::IpAddr;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    Event,
+    cef::parser,
+    mappers::MappingRegistry,
+    time::TimeFormat,
+};
+
+pub trait ToECS {
+    /// Converts into an Elastic Common Schema (ECS) document,
+    /// shaped like the ones Winlogbeat produces
+    fn to_ecs(&self) -> Value;
+
+    /// Converts into an ECS document, mapping the event data
+    /// with the given registry instead of the built-in one
+    fn to_ecs_with_mappings(&self, registry: &MappingRegistry) -> Value;
+}
+
+impl ToECS for Event {
+    fn to_ecs(&self) -> Value {
+        ecs_document(self, MappingRegistry::builtin())
+    }
+
+    fn to_ecs_with_mappings(&self, registry: &MappingRegistry) -> Value {
+        ecs_document(self, registry)
+    }
+}
+
+fn ecs_document(evt: &Event, registry: &MappingRegistry) -> Value {
+    //! Converts Windows Event into
+    //! an Elastic Common Schema (ECS) document
+    let system = &evt.System;
+    let provider = system.Provider.as_ref();
+    // The mapped event data, keyed by CEF field
+    let mut cef = parser::build_cef_extension(evt, registry).unwrap_or_else(|o| o.extension);
+    let mut take = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|k| cef.remove(*k))
+            .find(|v| !v.is_empty() && v != "-")
+            .unwrap_or_default()
+    };
+
+    let mut doc = Map::new();
+    set(&mut doc, "@timestamp", TimeFormat::Rfc3339.format(&system.TimeCreated));
+    set(&mut doc, "message", one_of(take(&["msg"]), &system.Event.EventName));
+
+    set(&mut doc, "event.kind", "event");
+    set(&mut doc, "event.code", system.Event.EventID.to_string());
+    set(&mut doc, "event.provider", provider.and_then(|p| p.Name.as_deref()));
+    set(&mut doc, "event.action", one_of(take(&["act"]), &system.Task.name));
+    let outcome = take(&["outcome"]).trim_start_matches('/').to_lowercase();
+    set(&mut doc, "event.outcome", outcome);
+    set(&mut doc, "log.level", system.Level.name.to_lowercase());
+
+    set(&mut doc, "host.name", system.Computer.as_str());
+
+    set(&mut doc, "user.name", take(&["duser", "suser"]));
+    set(&mut doc, "user.domain", take(&["dntdom", "sntdom"]));
+    set(&mut doc, "user.id", system.Security.as_ref().and_then(|s| s.UserID.as_deref()));
+
+    set(&mut doc, "process.pid", system.Execution.ProcessID);
+    set(&mut doc, "process.thread.id", system.Execution.ThreadID);
+    let executable = take(&["dproc"]);
+    let name = executable.rsplit(['\\', '/']).next().unwrap_or_default();
+    set(&mut doc, "process.name", name);
+    set(&mut doc, "process.executable", executable.as_str());
+
+    if let Ok(ip) = take(&["src"]).parse::<IpAddr>() {
+        set(&mut doc, "source.ip", ip.to_string());
+        set(&mut doc, "source.port", take(&["spt"]).parse::<u16>().ok().filter(|p| *p != 0));
+    }
+    if let Ok(ip) = take(&["dst"]).parse::<IpAddr>() {
+        set(&mut doc, "destination.ip", ip.to_string());
+        set(&mut doc, "destination.port", take(&["dpt"]).parse::<u16>().ok().filter(|p| *p != 0));
+    }
+
+    set(&mut doc, "winlog.channel", system.Channel.as_str());
+    set(&mut doc, "winlog.computer_name", system.Computer.as_str());
+    set(&mut doc, "winlog.event_id", system.Event.EventID.to_string());
+    set(&mut doc, "winlog.provider_name", provider.and_then(|p| p.Name.as_deref()));
+    set(&mut doc, "winlog.provider_guid", provider.and_then(|p| p.Guid.as_deref()));
+    set(&mut doc, "winlog.record_id", system.EventRecordID);
+    set(&mut doc, "winlog.version", system.Version);
+    set(&mut doc, "winlog.task", system.Task.name.as_str());
+    set(&mut doc, "winlog.opcode", system.Opcode.name.as_str());
+    set(&mut doc, "winlog.keywords", json!(system.Keywords.names));
+    set(&mut doc, "winlog.activity_id", system.Correlation.ActivityID.as_deref());
+    set(&mut doc, "winlog.process.pid", system.Execution.ProcessID);
+    set(&mut doc, "winlog.process.thread.id", system.Execution.ThreadID);
+    set(&mut doc, "winlog.user.identifier", system.Security.as_ref().and_then(|s| s.UserID.as_deref()));
+    set(&mut doc, "winlog.event_data", event_data(evt));
+    if let Some(user_data) = &evt.UserData {
+        set(&mut doc, "winlog.user_data", json!(user_data));
+    }
+
+    Value::Object(doc)
+}
+
+/// Get the event data as Winlogbeat does: named values as they are,
+/// unnamed ones as `param1`, `param2`, ...
+fn event_data(evt: &Event) -> Value {
+    match evt.EventData.as_ref() {
+        Some(data) => Value::Object(data.Data.to_object()),
+        None => Value::Null,
+    }
+}
+
+/// The first of the values which is set
+fn one_of(value: String, default: &str) -> String {
+    if value.is_empty() { default.to_string() } else { value }
+}
+
+/// Set a dotted path (eg: `user.name`) of the document.
+/// Missing values, empty strings, arrays and objects, and `-` are left out
+pub(crate) fn set<V: Into<Value>>(doc: &mut Map<String, Value>, path: &str, value: V) {
+    let value = value.into();
+    match &value {
+        Value::Null => return,
+        Value::String(s) if s.is_empty() || s == "-" => return,
+        Value::Array(a) if a.is_empty() => return,
+        Value::Object(o) if o.is_empty() => return,
+        _ => {}
+    }
+    match path.split_once('.') {
+        Some((parent, key)) => {
+            let child = doc.entry(parent).or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(child) = child {
+                set(child, key, value);
+            }
+        }
+        None => {
+            doc.insert(path.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_ecs() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let ecs = e.to_ecs();
+        assert_eq!(ecs["@timestamp"], "2021-01-26T11:17:29.4856969Z");
+        assert_eq!(ecs["event"]["code"], "4624");
+        assert_eq!(ecs["event"]["provider"], "Microsoft-Windows-Security-Auditing");
+        assert_eq!(ecs["event"]["outcome"], "success");
+        assert_eq!(ecs["host"]["name"], "DESKTOP-G089JUF");
+        assert_eq!(ecs["user"]["name"], "SYSTEM");
+        assert_eq!(ecs["user"]["domain"], "NT AUTHORITY");
+        assert_eq!(ecs["process"]["pid"], 648);
+        assert_eq!(ecs["winlog"]["channel"], "Security");
+        assert_eq!(ecs["winlog"]["record_id"], 26893);
+        assert_eq!(ecs["winlog"]["event_data"]["LogonType"], "5");
+        assert_eq!(ecs["source"]["ip"], "138.36.107.50");
+        // `IpPort` is 0
+        assert!(ecs["source"].get("port").is_none());
+    }
+
+    #[test]
+    fn test_golden_files() {
+        //! Every sample converts to the ECS document of its golden file
+        //! (`data/<sample>.ecs.json`). Run with `UPDATE_GOLDEN=1` to rewrite them
+        let mut samples = std::fs::read_dir("data")
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "xml"))
+            .collect::<Vec<_>>();
+        samples.sort();
+        assert!(!samples.is_empty());
+
+        for sample in samples {
+            let ecs = from_file(&sample).unwrap().to_ecs();
+            let golden = sample.with_extension("ecs.json");
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                let json = serde_json::to_string_pretty(&ecs).unwrap();
+                std::fs::write(&golden, json + "\n").unwrap();
+            }
+            let expected = std::fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("{}: {}", golden.display(), e));
+            let expected: Value = serde_json::from_str(&expected).unwrap();
+            assert_eq!(ecs, expected, "{}", golden.display());
+        }
+    }
+}
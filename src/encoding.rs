@@ -0,0 +1,81 @@
+use crate::error::{Error, Result};
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Decode raw XML bytes into a String.
+///
+/// Windows tools write exports either as UTF-8 (with or without BOM)
+/// or as UTF-16 with a BOM (eg: `wevtutil qe ... > out.xml` from PowerShell).
+pub(crate) fn decode(bytes: &[u8]) -> Result<String> {
+    let text = if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        utf8(rest)?
+    } else if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        utf16(rest, u16::from_le_bytes)?
+    } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        utf16(rest, u16::from_be_bytes)?
+    } else {
+        utf8(bytes)?
+    };
+    check_declared_encoding(&text)?;
+    Ok(text)
+}
+
+/// Make sure the encoding named in the XML declaration (if any)
+/// is one the text could have been decoded from.
+pub(crate) fn check_declared_encoding(xml: &str) -> Result<()> {
+    match declared_encoding(xml) {
+        Some(enc) => match enc.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" | "utf-16" | "utf-16le" | "utf-16be" => Ok(()),
+            _ => Err(Error::UnknownEncoding { encoding: enc.to_string() }),
+        },
+        None => Ok(()),
+    }
+}
+
+/// Get the value of the `encoding` pseudo-attribute of the XML declaration
+fn declared_encoding(xml: &str) -> Option<&str> {
+    let decl = xml.trim_start().strip_prefix("<?xml")?;
+    let decl = &decl[..decl.find("?>")?];
+    let rest = &decl[decl.find("encoding")? + "encoding".len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let rest = &rest[1..];
+    Some(&rest[..rest.find(quote)?])
+}
+
+fn utf8(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::UnknownEncoding {
+        encoding: "non UTF-8 data without a byte order mark".to_string(),
+    })
+}
+
+fn utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Result<String> {
+    let units = bytes
+        .chunks(2)
+        .map(|c| if c.len() == 2 { from_bytes([c[0], c[1]]) } else { 0xFFFD })
+        .collect::<Vec<u16>>();
+    String::from_utf16(&units).map_err(|_| Error::UnknownEncoding {
+        encoding: "invalid UTF-16 data".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?><Event/>"#;
+        assert_eq!(decode(xml.as_bytes()).unwrap(), xml);
+
+        let mut utf16 = UTF16LE_BOM.to_vec();
+        utf16.extend(xml.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        assert_eq!(decode(&utf16).unwrap(), xml);
+
+        let latin = r#"<?xml version="1.0" encoding="ISO-8859-1"?><Event/>"#;
+        let err = decode(latin.as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::UnknownEncoding { ref encoding } if encoding == "ISO-8859-1"));
+    }
+}
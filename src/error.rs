@@ -0,0 +1,299 @@
+use std::{
+    cell::Cell,
+    fmt,
+    io::{self, BufRead, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use quick_xml::{de::DeError, Error as XmlError};
+
+/// Result type returned by the deserialization functions of this crate
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Position in the XML input at which an error was detected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column (in bytes) within the line
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {} (offset {})", self.line, self.column, self.offset)
+    }
+}
+
+/// Errors raised while reading and deserializing Windows Event Log XML
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input could not be opened or read
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// The input is not well formed XML or does not have the shape of an `<Event>`
+    Xml {
+        location: Location,
+        field: Option<String>,
+        message: String,
+    },
+    /// A required field of the `<System>` block is absent
+    MissingField {
+        location: Location,
+        field: String,
+    },
+    /// A numeric field holds a value that does not parse as a number
+    InvalidNumber {
+        location: Location,
+        field: String,
+        message: String,
+    },
+    /// The input is in (or declares) an encoding this crate cannot decode
+    UnknownEncoding {
+        encoding: String,
+    },
+    /// The EVTX binary file is corrupt or uses an unsupported construct
+    Evtx {
+        offset: u64,
+        message: String,
+    },
+    /// A user mapping file could not be parsed
+    Mapping {
+        source: String,
+        message: String,
+    },
+    /// The event needs more CEF custom strings, numbers or dates than the spec defines
+    CefOverflow {
+        fields: Vec<String>,
+    },
+    /// The GELF message does not fit in the 128 chunks of a UDP message
+    GelfTooLarge {
+        size: usize,
+        max_datagram_size: usize,
+    },
+    /// A `strftime` date format holds an unknown specifier
+    DateFormat {
+        format: String,
+    },
+    /// A document could not be serialized to JSON
+    Json {
+        source: serde_json::Error,
+    },
+}
+
+/// Fieldless discriminant of [`Error`], convenient to route failed records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Io,
+    Xml,
+    MissingField,
+    InvalidNumber,
+    UnknownEncoding,
+    Evtx,
+    Mapping,
+    CefOverflow,
+    GelfTooLarge,
+    DateFormat,
+    Json,
+}
+
+impl Error {
+    pub(crate) fn io<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        Error::Io { path: Some(path.as_ref().to_path_buf()), source }
+    }
+
+    /// Get the kind of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io { .. } => ErrorKind::Io,
+            Error::Xml { .. } => ErrorKind::Xml,
+            Error::MissingField { .. } => ErrorKind::MissingField,
+            Error::InvalidNumber { .. } => ErrorKind::InvalidNumber,
+            Error::UnknownEncoding { .. } => ErrorKind::UnknownEncoding,
+            Error::Evtx { .. } => ErrorKind::Evtx,
+            Error::Mapping { .. } => ErrorKind::Mapping,
+            Error::CefOverflow { .. } => ErrorKind::CefOverflow,
+            Error::GelfTooLarge { .. } => ErrorKind::GelfTooLarge,
+            Error::DateFormat { .. } => ErrorKind::DateFormat,
+            Error::Json { .. } => ErrorKind::Json,
+        }
+    }
+
+    /// Get the location in the input where the error was detected, if known
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::Xml { location, .. }
+            | Error::MissingField { location, .. }
+            | Error::InvalidNumber { location, .. } => Some(*location),
+            _ => None,
+        }
+    }
+
+    /// Get the path of the offending field (eg: `System.EventRecordID`), if known
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            Error::MissingField { field, .. } | Error::InvalidNumber { field, .. } => Some(field),
+            Error::Xml { field, .. } => field.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Convert a deserialization error into an `Error`, attaching
+    /// the field path and the position reached in the input
+    pub(crate) fn from_de(err: serde_path_to_error::Error<DeError>, location: Location) -> Self {
+        let path = err.path().to_string();
+        let field = if path == "." || path.is_empty() { None } else { Some(path) };
+        match err.into_inner() {
+            DeError::Xml(XmlError::Io(source)) => Error::Io { path: None, source },
+            DeError::Int(e) => Error::InvalidNumber {
+                location,
+                field: field.unwrap_or_default(),
+                message: e.to_string(),
+            },
+            DeError::Float(e) => Error::InvalidNumber {
+                location,
+                field: field.unwrap_or_default(),
+                message: e.to_string(),
+            },
+            DeError::Custom(msg) => {
+                if let Some(missing) = parse_missing_field(&msg) {
+                    let field = match field {
+                        Some(parent) => format!("{}.{}", parent, missing),
+                        None => missing.to_string(),
+                    };
+                    Error::MissingField { location, field }
+                } else {
+                    Error::Xml { location, field, message: msg }
+                }
+            }
+            e => Error::Xml { location, field, message: e.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path: Some(p), source } => write!(f, "{}: {}", p.display(), source),
+            Error::Io { path: None, source } => write!(f, "I/O error: {}", source),
+            Error::Xml { location, field: Some(field), message } => {
+                write!(f, "invalid XML at {} in `{}`: {}", location, field, message)
+            }
+            Error::Xml { location, field: None, message } => {
+                write!(f, "invalid XML at {}: {}", location, message)
+            }
+            Error::MissingField { location, field } => {
+                write!(f, "missing field `{}` at {}", field, location)
+            }
+            Error::InvalidNumber { location, field, message } => {
+                write!(f, "invalid number in `{}` at {}: {}", field, location, message)
+            }
+            Error::UnknownEncoding { encoding } => write!(f, "unknown encoding `{}`", encoding),
+            Error::Evtx { offset, message } => {
+                write!(f, "invalid EVTX data at offset {:#x}: {}", offset, message)
+            }
+            Error::Mapping { source, message } => write!(f, "invalid {}: {}", source, message),
+            Error::CefOverflow { fields } => {
+                write!(f, "no CEF custom field slot left for {}", fields.join(", "))
+            }
+            Error::GelfTooLarge { size, max_datagram_size } => write!(
+                f,
+                "GELF message of {} bytes does not fit in 128 datagrams of {} bytes",
+                size, max_datagram_size
+            ),
+            Error::DateFormat { format } => write!(f, "invalid date format `{}`", format),
+            Error::Json { source } => write!(f, "JSON serialization failed: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Json { source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(source: serde_json::Error) -> Self {
+        Error::Json { source }
+    }
+}
+
+/// Extract `x` out of serde's "missing field `x`" message
+fn parse_missing_field(msg: &str) -> Option<&str> {
+    msg.strip_prefix("missing field `")?.strip_suffix('`')
+}
+
+/// A `BufRead` adapter that keeps track of how far the XML reader has consumed
+/// its input, so errors can point at a line/column.
+pub(crate) struct Tracked<R> {
+    inner: R,
+    location: Rc<Cell<Location>>,
+}
+
+impl<R: BufRead> Tracked<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            location: Rc::new(Cell::new(Location { offset: 0, line: 1, column: 1 })),
+        }
+    }
+
+    /// Shared handle on the current location
+    pub(crate) fn location(&self) -> Rc<Cell<Location>> {
+        self.location.clone()
+    }
+}
+
+impl<R: BufRead> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Tracked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            let mut loc = self.location.get();
+            for &b in &buf[..amt.min(buf.len())] {
+                if b == b'\n' {
+                    loc.line += 1;
+                    loc.column = 1;
+                } else {
+                    loc.column += 1;
+                }
+            }
+            loc.offset += amt;
+            self.location.set(loc);
+        }
+        self.inner.consume(amt)
+    }
+}
@@ -0,0 +1,413 @@
+use std::{collections::HashMap, convert::TryInto, rc::Rc};
+
+use crate::evtx::value::{format_value, Value};
+
+const TOKEN_EOF: u8 = 0x00;
+const TOKEN_OPEN_START_ELEMENT: u8 = 0x01;
+const TOKEN_CLOSE_START_ELEMENT: u8 = 0x02;
+const TOKEN_CLOSE_EMPTY_ELEMENT: u8 = 0x03;
+const TOKEN_END_ELEMENT: u8 = 0x04;
+const TOKEN_VALUE: u8 = 0x05;
+const TOKEN_ATTRIBUTE: u8 = 0x06;
+const TOKEN_CDATA: u8 = 0x07;
+const TOKEN_CHAR_REF: u8 = 0x08;
+const TOKEN_ENTITY_REF: u8 = 0x09;
+const TOKEN_PI_TARGET: u8 = 0x0a;
+const TOKEN_PI_DATA: u8 = 0x0b;
+const TOKEN_TEMPLATE_INSTANCE: u8 = 0x0c;
+const TOKEN_NORMAL_SUBSTITUTION: u8 = 0x0d;
+const TOKEN_OPTIONAL_SUBSTITUTION: u8 = 0x0e;
+const TOKEN_FRAGMENT_HEADER: u8 = 0x0f;
+/// Set on element/attribute/value tokens when more of the same kind follow
+const TOKEN_MORE_FLAG: u8 = 0x40;
+const TOKEN_ATTRIBUTE_MORE: u8 = TOKEN_ATTRIBUTE | TOKEN_MORE_FLAG;
+
+const VALUE_BINXML: u8 = 0x21;
+
+/// Deepest nesting of elements, templates and embedded fragments accepted
+const MAX_DEPTH: usize = 64;
+
+/// A parsed BinXML node
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    Element {
+        name: String,
+        attributes: Vec<(String, Vec<Node>)>,
+        children: Vec<Node>,
+    },
+    Text(String),
+    CData(String),
+    CharRef(u16),
+    EntityRef(String),
+    Substitution {
+        id: u16,
+        optional: bool,
+    },
+    Template {
+        nodes: Rc<Vec<Node>>,
+        values: Vec<Value>,
+    },
+}
+
+/// BinXML parser over one chunk. Name and template offsets
+/// found in the stream are relative to the start of the chunk.
+pub(crate) struct Parser<'a> {
+    chunk: &'a [u8],
+    pos: usize,
+    templates: &'a mut HashMap<u32, Rc<Vec<Node>>>,
+    /// Offsets of the template definitions being parsed
+    defining: Vec<u32>,
+    depth: usize,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(
+        chunk: &'a [u8],
+        pos: usize,
+        templates: &'a mut HashMap<u32, Rc<Vec<Node>>>,
+    ) -> Self {
+        Self {
+            chunk,
+            pos,
+            templates,
+            defining: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Run `parse` one nesting level deeper, failing past `MAX_DEPTH`
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<T>) -> ParseResult<T> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "BinXML nested deeper than {} levels at offset {}",
+                MAX_DEPTH, self.pos
+            ));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn bytes(&mut self, len: usize) -> ParseResult<&'a [u8]> {
+        let chunk: &'a [u8] = self.chunk;
+        let b = chunk
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| format!("unexpected end of chunk at offset {}", self.pos))?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn peek(&self) -> ParseResult<u8> {
+        self.chunk
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| format!("unexpected end of chunk at offset {}", self.pos))
+    }
+
+    fn u8(&mut self) -> ParseResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> ParseResult<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> ParseResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn utf16(&mut self, chars: usize) -> ParseResult<String> {
+        let units = self
+            .bytes(chars * 2)?
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Read a name: either defined inline right here, or a reference
+    /// to a name defined earlier in the chunk
+    fn name(&mut self) -> ParseResult<String> {
+        let offset = self.u32()? as usize;
+        if offset == self.pos {
+            self.name_at_cursor()
+        } else {
+            let saved = self.pos;
+            self.pos = offset;
+            let name = self.name_at_cursor();
+            self.pos = saved;
+            name
+        }
+    }
+
+    fn name_at_cursor(&mut self) -> ParseResult<String> {
+        let _next_offset = self.u32()?;
+        let _hash = self.u16()?;
+        let len = self.u16()? as usize;
+        let name = self.utf16(len)?;
+        let _terminator = self.u16()?;
+        Ok(name)
+    }
+
+    /// Parse a BinXML fragment up to its end-of-fragment token.
+    /// `embedded` fragments (BinXml substitution values) have no
+    /// dependency identifier in their element tokens.
+    pub(crate) fn fragment(&mut self, embedded: bool) -> ParseResult<Vec<Node>> {
+        self.nested(|p| p.fragment_nodes(embedded))
+    }
+
+    fn fragment_nodes(&mut self, embedded: bool) -> ParseResult<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            match self.peek()? {
+                TOKEN_EOF => {
+                    self.pos += 1;
+                    return Ok(nodes);
+                }
+                TOKEN_FRAGMENT_HEADER => {
+                    self.bytes(4)?;
+                }
+                TOKEN_END_ELEMENT => {
+                    return Err(format!("unbalanced end element at offset {}", self.pos))
+                }
+                _ => nodes.extend(self.node(embedded)?),
+            }
+        }
+    }
+
+    /// Parse a single content node, processing instructions yield nothing
+    fn node(&mut self, embedded: bool) -> ParseResult<Option<Node>> {
+        let token = self.peek()?;
+        let node = match token & !TOKEN_MORE_FLAG {
+            TOKEN_OPEN_START_ELEMENT => self.nested(|p| p.element(embedded))?,
+            TOKEN_VALUE => {
+                self.pos += 1;
+                let ty = self.u8()?;
+                let len = self.u16()? as usize;
+                let text = match ty {
+                    0x01 => self.utf16(len)?,
+                    _ => return Err(format!("unsupported value type {:#x} in value token", ty)),
+                };
+                Node::Text(text)
+            }
+            TOKEN_CDATA => {
+                self.pos += 1;
+                let len = self.u16()? as usize;
+                Node::CData(self.utf16(len)?)
+            }
+            TOKEN_CHAR_REF => {
+                self.pos += 1;
+                Node::CharRef(self.u16()?)
+            }
+            TOKEN_ENTITY_REF => {
+                self.pos += 1;
+                Node::EntityRef(self.name()?)
+            }
+            TOKEN_PI_TARGET => {
+                self.pos += 1;
+                self.name()?;
+                if self.peek()? == TOKEN_PI_DATA {
+                    self.pos += 1;
+                    let len = self.u16()? as usize;
+                    self.utf16(len)?;
+                }
+                return Ok(None);
+            }
+            TOKEN_TEMPLATE_INSTANCE => self.template_instance()?,
+            TOKEN_NORMAL_SUBSTITUTION | TOKEN_OPTIONAL_SUBSTITUTION => {
+                self.pos += 1;
+                let id = self.u16()?;
+                let _value_type = self.u8()?;
+                Node::Substitution {
+                    id,
+                    optional: token == TOKEN_OPTIONAL_SUBSTITUTION,
+                }
+            }
+            _ => return Err(format!("unexpected token {:#x} at offset {}", token, self.pos)),
+        };
+        Ok(Some(node))
+    }
+
+    fn element(&mut self, embedded: bool) -> ParseResult<Node> {
+        let token = self.u8()?;
+        if !embedded {
+            let _dependency_id = self.u16()?;
+        }
+        let _data_size = self.u32()?;
+        let name = self.name()?;
+
+        let mut attributes = Vec::new();
+        if token & TOKEN_MORE_FLAG != 0 {
+            let _attributes_size = self.u32()?;
+            loop {
+                let attr_token = self.u8()?;
+                if attr_token & !TOKEN_MORE_FLAG != TOKEN_ATTRIBUTE {
+                    return Err(format!("expected attribute at offset {}", self.pos - 1));
+                }
+                let attr_name = self.name()?;
+                let mut value = Vec::new();
+                while !matches!(
+                    self.peek()?,
+                    TOKEN_CLOSE_START_ELEMENT
+                        | TOKEN_CLOSE_EMPTY_ELEMENT
+                        | TOKEN_ATTRIBUTE
+                        | TOKEN_ATTRIBUTE_MORE
+                ) {
+                    value.extend(self.node(embedded)?);
+                }
+                attributes.push((attr_name, value));
+                if attr_token & TOKEN_MORE_FLAG == 0 {
+                    break;
+                }
+            }
+        }
+
+        let mut children = Vec::new();
+        match self.u8()? {
+            TOKEN_CLOSE_EMPTY_ELEMENT => {}
+            TOKEN_CLOSE_START_ELEMENT => loop {
+                if self.peek()? == TOKEN_END_ELEMENT {
+                    self.pos += 1;
+                    break;
+                }
+                children.extend(self.node(embedded)?);
+            },
+            t => return Err(format!("unexpected token {:#x} closing element `{}`", t, name)),
+        }
+
+        Ok(Node::Element { name, attributes, children })
+    }
+
+    fn template_instance(&mut self) -> ParseResult<Node> {
+        let _token = self.u8()?;
+        let _unknown = self.u8()?;
+        let _template_id = self.u32()?;
+        let def_offset = self.u32()?;
+
+        let nodes = if def_offset as usize == self.pos {
+            // Definition follows inline
+            let nodes = self.template_definition(def_offset)?;
+            let data_size = u32::from_le_bytes(
+                self.chunk[def_offset as usize + 20..def_offset as usize + 24].try_into().unwrap(),
+            );
+            self.pos = def_offset as usize + 24 + data_size as usize;
+            nodes
+        } else if let Some(nodes) = self.templates.get(&def_offset) {
+            nodes.clone()
+        } else {
+            let saved = self.pos;
+            let nodes = self.template_definition(def_offset)?;
+            self.pos = saved;
+            nodes
+        };
+
+        let count = self.u32()? as usize;
+        let mut descriptors = Vec::with_capacity(count);
+        for _ in 0..count {
+            let size = self.u16()? as usize;
+            let ty = self.u8()?;
+            let _padding = self.u8()?;
+            descriptors.push((size, ty));
+        }
+        let mut values = Vec::with_capacity(count);
+        for (size, ty) in descriptors {
+            let start = self.pos;
+            let value = if ty == VALUE_BINXML && size > 0 {
+                Value::BinXml(self.fragment(true)?)
+            } else {
+                match format_value(ty, self.bytes(size)?) {
+                    Some(text) => Value::Text(text),
+                    None => Value::Null,
+                }
+            };
+            self.pos = start + size;
+            values.push(value);
+        }
+
+        Ok(Node::Template { nodes, values })
+    }
+
+    fn template_definition(&mut self, offset: u32) -> ParseResult<Rc<Vec<Node>>> {
+        if self.defining.contains(&offset) {
+            return Err(format!("template at offset {} references itself", offset));
+        }
+        self.pos = offset as usize;
+        let _next_offset = self.u32()?;
+        let _guid = self.bytes(16)?;
+        let _data_size = self.u32()?;
+        self.defining.push(offset);
+        let nodes = self.fragment(false);
+        self.defining.pop();
+        let nodes = Rc::new(nodes?);
+        self.templates.insert(offset, nodes.clone());
+        Ok(nodes)
+    }
+}
+
+/// Render parsed nodes to XML text, `values` being the substitution
+/// values of the innermost template instance.
+pub(crate) fn render(nodes: &[Node], values: &[Value], out: &mut String) -> ParseResult<()> {
+    render_nested(nodes, values, out, 0)
+}
+
+fn render_nested(nodes: &[Node], values: &[Value], out: &mut String, depth: usize) -> ParseResult<()> {
+    if depth == MAX_DEPTH {
+        return Err(format!("BinXML nested deeper than {} levels", MAX_DEPTH));
+    }
+    let depth = depth + 1;
+    for node in nodes {
+        match node {
+            Node::Element { name, attributes, children } => {
+                out.push('<');
+                out.push_str(name);
+                for (attr_name, value) in attributes {
+                    if is_null(value, values) {
+                        continue;
+                    }
+                    let mut text = String::new();
+                    render_nested(value, values, &mut text, depth)?;
+                    out.push_str(&format!(" {}=\"{}\"", attr_name, text.replace('"', "&quot;")));
+                }
+                let mut inner = String::new();
+                render_nested(children, values, &mut inner, depth)?;
+                if inner.is_empty() {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    out.push_str(&inner);
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+            Node::Text(t) => out.push_str(&escape(t)),
+            Node::CData(t) => out.push_str(&format!("<![CDATA[{}]]>", t)),
+            Node::CharRef(c) => out.push_str(&format!("&#{};", c)),
+            Node::EntityRef(e) => out.push_str(&format!("&{};", e)),
+            Node::Substitution { id, .. } => match values.get(*id as usize) {
+                Some(Value::Text(t)) => out.push_str(&escape(t)),
+                // An embedded fragment does not see the values it is one of,
+                // its own templates carry theirs
+                Some(Value::BinXml(inner)) => render_nested(inner, &[], out, depth)?,
+                _ => {}
+            },
+            Node::Template { nodes, values } => render_nested(nodes, values, out, depth)?,
+        }
+    }
+    Ok(())
+}
+
+/// An attribute made only of optional substitutions with no value is omitted
+fn is_null(value: &[Node], values: &[Value]) -> bool {
+    value.iter().all(|n| match n {
+        Node::Substitution { id, optional: true } => {
+            values.get(*id as usize).map(|v| v.is_null()).unwrap_or(true)
+        }
+        _ => false,
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,335 @@
+//! Native reader for Windows XML Event Log (EVTX) binary files.
+//!
+//! An EVTX file is a 4 KiB file header followed by 64 KiB chunks. Each chunk
+//! holds event records whose content is BinXML: a tokenized XML where names
+//! and templates are shared across the records of a chunk. Every record is
+//! rendered back to its XML text and deserialized like any other event.
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    de::Event,
+    deserialize,
+    error::{Error, Result},
+    mappers::MappingRegistry,
+};
+
+mod binxml;
+mod value;
+
+use binxml::{render, Node, Parser};
+
+const FILE_SIGNATURE: &[u8] = b"ElfFile\0";
+const CHUNK_SIGNATURE: &[u8] = b"ElfChnk\0";
+const RECORD_SIGNATURE: &[u8] = &[0x2a, 0x2a, 0x00, 0x00];
+const FILE_HEADER_SIZE: usize = 4096;
+const CHUNK_SIZE: usize = 65536;
+const CHUNK_HEADER_SIZE: usize = 512;
+
+/// Streaming reader over the event records of an EVTX file.
+///
+/// Only one chunk (64 KiB) is decoded and held in memory at a time.
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{EvtxReader, ToCEF};
+///
+/// let reader = EvtxReader::from_file("data/winevt1.evtx").unwrap();
+/// for event in reader {
+///     println!("{}", event.unwrap().to_cef());
+/// }
+/// ```
+pub struct EvtxReader<R: Read> {
+    reader: R,
+    /// File offset of the next chunk to read
+    offset: u64,
+    records: VecDeque<Result<Event>>,
+    registry: MappingRegistry,
+    done: bool,
+}
+
+impl EvtxReader<BufReader<File>> {
+    /// Open an EVTX file
+    pub fn from_file<P: AsRef<Path>>(evtx_path: P) -> Result<Self> {
+        let f = File::open(evtx_path.as_ref()).map_err(|e| Error::io(&evtx_path, e))?;
+        Self::new(BufReader::new(f))
+    }
+}
+
+impl<R: Read> EvtxReader<R> {
+    /// Read and validate the file header, leaving the reader on the first chunk
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = vec![0u8; FILE_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if &header[..8] != FILE_SIGNATURE {
+            return Err(evtx_error(0, "not an EVTX file (bad file signature)"));
+        }
+        let major = u16::from_le_bytes([header[38], header[39]]);
+        if major != 3 {
+            return Err(evtx_error(38, format!("unsupported EVTX version {}", major)));
+        }
+        let checksum = u32::from_le_bytes(header[124..128].try_into().unwrap());
+        if crc32fast::hash(&header[..120]) != checksum {
+            return Err(evtx_error(124, "file header checksum mismatch"));
+        }
+
+        Ok(Self {
+            reader,
+            offset: FILE_HEADER_SIZE as u64,
+            records: VecDeque::new(),
+            registry: MappingRegistry::default(),
+            done: false,
+        })
+    }
+
+    /// Name events and tasks with the given mapping registry
+    pub fn with_mappings(mut self, registry: &MappingRegistry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Read the next chunk and queue up its records.
+    /// Returns `false` once the end of the file is reached.
+    fn read_chunk(&mut self) -> bool {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut filled = 0;
+        while filled < CHUNK_SIZE {
+            match self.reader.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.records.push_back(Err(e.into()));
+                    return false;
+                }
+            }
+        }
+        if filled == 0 {
+            return false;
+        }
+        if filled < CHUNK_SIZE {
+            self.records.push_back(Err(evtx_error(self.offset, "truncated chunk")));
+            return false;
+        }
+        let chunk_offset = self.offset;
+        self.offset += CHUNK_SIZE as u64;
+
+        if &chunk[..8] != CHUNK_SIGNATURE {
+            // Pre-allocated but never used chunk
+            return true;
+        }
+        match parse_chunk(&chunk, chunk_offset, &self.registry) {
+            Ok(records) => self.records.extend(records),
+            Err(e) => self.records.push_back(Err(e)),
+        }
+        true
+    }
+}
+
+impl<R: Read> Iterator for EvtxReader<R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.records.is_empty() && !self.done {
+            self.done = !self.read_chunk();
+        }
+        self.records.pop_front()
+    }
+}
+
+/// Validate a chunk and decode all of its records
+fn parse_chunk(chunk: &[u8], chunk_offset: u64, registry: &MappingRegistry) -> Result<Vec<Result<Event>>> {
+    let u32_at = |pos: usize| u32::from_le_bytes(chunk[pos..pos + 4].try_into().unwrap());
+
+    let header_crc = {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&chunk[..120]);
+        hasher.update(&chunk[128..CHUNK_HEADER_SIZE]);
+        hasher.finalize()
+    };
+    if header_crc != u32_at(124) {
+        return Err(evtx_error(chunk_offset + 124, "chunk header checksum mismatch"));
+    }
+    let free_space = (u32_at(48) as usize).min(CHUNK_SIZE);
+    if free_space < CHUNK_HEADER_SIZE {
+        return Err(evtx_error(chunk_offset + 48, "invalid free space offset"));
+    }
+    if crc32fast::hash(&chunk[CHUNK_HEADER_SIZE..free_space]) != u32_at(52) {
+        return Err(evtx_error(chunk_offset + 52, "chunk records checksum mismatch"));
+    }
+
+    let mut templates: HashMap<u32, Rc<Vec<Node>>> = HashMap::new();
+    let mut records = Vec::new();
+    let mut pos = CHUNK_HEADER_SIZE;
+    while pos + 24 <= free_space && &chunk[pos..pos + 4] == RECORD_SIGNATURE {
+        let size = u32_at(pos + 4) as usize;
+        if size < 28 || pos + size > free_space {
+            records.push(Err(evtx_error(chunk_offset + pos as u64, "invalid record size")));
+            break;
+        }
+        let record = Parser::new(&chunk[..pos + size - 4], pos + 24, &mut templates)
+            .fragment(false)
+            .and_then(|nodes| {
+                let mut xml = String::new();
+                render(&nodes, &[], &mut xml).map(|_| xml)
+            })
+            .map_err(|e| evtx_error(chunk_offset + pos as u64, e))
+            .and_then(|xml| deserialize(xml.as_bytes(), registry));
+        records.push(record);
+        pos += size;
+    }
+
+    Ok(records)
+}
+
+fn evtx_error<S: Into<String>>(offset: u64, message: S) -> Error {
+    Error::Evtx { offset, message: message.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, TimeFormat};
+
+    const TOKEN_TEMPLATE_INSTANCE: u8 = 0x0c;
+
+    /// Files exported from Windows, each checked against its XML export
+    /// (see `data/evtx/README.md`)
+    #[test]
+    fn test_windows_exports() {
+        for entry in std::fs::read_dir("data/evtx").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("evtx".as_ref()) {
+                continue;
+            }
+            let events = EvtxReader::from_file(&path)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            let exported = crate::EventReader::from_file(path.with_extension("xml"))
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(events.len(), exported.len(), "{}", path.display());
+            for (e, x) in events.iter().zip(&exported) {
+                let (e, x) = (serde_json::to_value(e).unwrap(), serde_json::to_value(x).unwrap());
+                assert_eq!(e, x, "{}", path.display());
+            }
+        }
+    }
+
+    /// `winevt1.evtx` is built by hand, not exported from Windows
+    #[test]
+    fn test_evtx_records() {
+        let events = EvtxReader::from_file("data/winevt1.evtx")
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(events.len(), 3);
+
+        let e = &events[0];
+        assert_eq!(e.System.Event.EventID, 4624);
+        assert_eq!(e.System.EventRecordID, 26893);
+        assert_eq!(TimeFormat::Rfc3339.format(&e.System.TimeCreated), "2021-01-26T11:17:29.4856969Z");
+        assert_eq!(e.System.Keywords.names, ["Audit Success"]);
+        let data = e.EventData.as_ref().unwrap();
+        assert_eq!(data.Data.get("TargetUserName"), Some("SYSTEM"));
+        assert_eq!(data.Data.get("ProcessName"), Some("C:\\Windows\\System32\\services.exe"));
+        // Renders to the same event as its XML export
+        let xml = crate::from_file("data/winevt1.xml").unwrap();
+        assert_eq!(serde_json::to_value(e).unwrap(), serde_json::to_value(&xml).unwrap());
+
+        // Second record reuses the template defined by the first one
+        let e = &events[1];
+        assert_eq!(e.System.Event.EventID, 4624);
+        assert_eq!(e.System.EventRecordID, 26894);
+        assert_eq!(e.EventData.as_ref().unwrap().Data.get("TargetUserName"), Some("alice"));
+
+        // Third record carries its UserData as an embedded BinXML value
+        let e = &events[2];
+        assert_eq!(e.System.Event.EventID, 2);
+        let user_data = e.UserData.as_ref().unwrap();
+        assert_eq!(user_data.value("CbsPackageChangeState/PackageIdentifier"), Some("KB4586876"));
+    }
+
+    #[test]
+    fn test_not_evtx() {
+        let mut padded = std::fs::read("data/winevt1.xml").unwrap();
+        padded.resize(FILE_HEADER_SIZE, 0);
+        let err = EvtxReader::new(padded.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Evtx);
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut data = std::fs::read("data/winevt1.evtx").unwrap();
+        data.truncate(FILE_HEADER_SIZE + 1000);
+        let results = EvtxReader::new(data.as_slice()).unwrap().collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        match &results[0] {
+            Err(Error::Evtx { offset, message }) => {
+                assert_eq!(*offset, FILE_HEADER_SIZE as u64);
+                assert_eq!(message, "truncated chunk");
+            }
+            other => panic!("expected a truncated chunk error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_self_referencing_template() {
+        let mut chunk = vec![TOKEN_TEMPLATE_INSTANCE, 1, 0, 0, 0, 0];
+        // Definition inline at offset 10: next offset, guid, data size
+        chunk.extend(&10u32.to_le_bytes());
+        chunk.extend(&[0; 20]);
+        chunk.extend(&8u32.to_le_bytes());
+        // whose fragment instantiates the template being defined
+        chunk.extend(&[0x0f, 1, 1, 0, TOKEN_TEMPLATE_INSTANCE, 1, 0, 0, 0, 0]);
+        chunk.extend(&10u32.to_le_bytes());
+        let err = Parser::new(&chunk, 0, &mut HashMap::new()).fragment(false).unwrap_err();
+        assert_eq!(err, "template at offset 10 references itself");
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |levels: usize| {
+            let mut chunk = Vec::new();
+            for _ in 0..levels {
+                chunk.extend(&[0x01, 0, 0, 0, 0]);
+                let name_offset = chunk.len() as u32 + 4;
+                chunk.extend(&name_offset.to_le_bytes());
+                chunk.extend(&[0, 0, 0, 0, 0, 0, 1, 0, b'a', 0, 0, 0, 0x02]);
+            }
+            chunk.extend(vec![0x04; levels]);
+            chunk.push(0x00);
+            Parser::new(&chunk, 0, &mut HashMap::new()).fragment(true)
+        };
+        assert!(nested(32).is_ok());
+        assert!(nested(100).unwrap_err().starts_with("BinXML nested deeper than 64 levels"));
+    }
+
+    #[test]
+    fn test_self_referencing_substitution() {
+        // Template whose only node is substitution 0, inline at offset 10
+        let mut chunk = vec![TOKEN_TEMPLATE_INSTANCE, 1, 0, 0, 0, 0];
+        chunk.extend(&10u32.to_le_bytes());
+        chunk.extend(&[0; 20]);
+        chunk.extend(&9u32.to_le_bytes());
+        chunk.extend(&[0x0f, 1, 1, 0, 0x0d, 0, 0, 0x21, 0x00]);
+        // Value 0 is an embedded fragment made of substitution 0 again
+        chunk.extend(&1u32.to_le_bytes());
+        chunk.extend(&[9, 0, 0x21, 0]);
+        chunk.extend(&[0x0f, 1, 1, 0, 0x0d, 0, 0, 0x21, 0x00]);
+        chunk.push(0x00);
+        let nodes = Parser::new(&chunk, 0, &mut HashMap::new()).fragment(false).unwrap();
+        let mut xml = String::new();
+        render(&nodes, &[], &mut xml).unwrap();
+        assert_eq!(xml, "");
+    }
+}
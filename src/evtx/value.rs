@@ -0,0 +1,179 @@
+use std::convert::TryInto;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+
+use crate::evtx::binxml::Node;
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_EPOCH_DELTA: i64 = 11_644_473_600;
+const ARRAY_FLAG: u8 = 0x80;
+
+/// A decoded substitution value
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Null,
+    Text(String),
+    BinXml(Vec<Node>),
+}
+
+impl Value {
+    pub(crate) fn is_null(&self) -> bool {
+        match self {
+            Value::Null => true,
+            Value::Text(t) => t.is_empty(),
+            Value::BinXml(n) => n.is_empty(),
+        }
+    }
+}
+
+/// Render a fixed type value (anything but BinXml) to the text `wevtutil` would print
+pub(crate) fn format_value(ty: u8, data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    if ty & ARRAY_FLAG != 0 {
+        return format_array(ty & !ARRAY_FLAG, data);
+    }
+    let s = match ty {
+        0x00 => return None,
+        0x01 => utf16(data),
+        0x02 => data
+            .iter()
+            .take_while(|b| **b != 0)
+            .map(|b| *b as char)
+            .collect(),
+        0x03 => (data[0] as i8).to_string(),
+        0x04 => data[0].to_string(),
+        0x05 => i16::from_le_bytes(data.get(..2)?.try_into().ok()?).to_string(),
+        0x06 => u16::from_le_bytes(data.get(..2)?.try_into().ok()?).to_string(),
+        0x07 => i32::from_le_bytes(data.get(..4)?.try_into().ok()?).to_string(),
+        0x08 => u32::from_le_bytes(data.get(..4)?.try_into().ok()?).to_string(),
+        0x09 => i64::from_le_bytes(data.get(..8)?.try_into().ok()?).to_string(),
+        0x0a => u64::from_le_bytes(data.get(..8)?.try_into().ok()?).to_string(),
+        0x0b => f32::from_le_bytes(data.get(..4)?.try_into().ok()?).to_string(),
+        0x0c => f64::from_le_bytes(data.get(..8)?.try_into().ok()?).to_string(),
+        0x0d => (u32::from_le_bytes(data.get(..4)?.try_into().ok()?) != 0).to_string(),
+        0x0e => data.iter().map(|b| format!("{:02X}", b)).collect(),
+        0x0f => guid(data.get(..16)?),
+        0x10 if data.len() == 4 => {
+            format!("0x{:08x}", u32::from_le_bytes(data.try_into().ok()?))
+        }
+        0x10 => format!("0x{:016x}", u64::from_le_bytes(data.get(..8)?.try_into().ok()?)),
+        0x11 => filetime(u64::from_le_bytes(data.get(..8)?.try_into().ok()?))?,
+        0x12 => systemtime(data.get(..16)?)?,
+        0x13 => sid(data)?,
+        0x14 => format!("0x{:x}", u32::from_le_bytes(data.get(..4)?.try_into().ok()?)),
+        0x15 => format!("0x{:x}", u64::from_le_bytes(data.get(..8)?.try_into().ok()?)),
+        _ => data.iter().map(|b| format!("{:02X}", b)).collect(),
+    };
+    Some(s)
+}
+
+/// Arrays are rendered as a comma separated list of their items
+fn format_array(ty: u8, data: &[u8]) -> Option<String> {
+    let items = match ty {
+        0x01 => data
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+            .collect::<Vec<u16>>()
+            .split(|u| *u == 0)
+            .filter(|s| !s.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect::<Vec<_>>(),
+        0x02 => data
+            .split(|b| *b == 0)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.iter().map(|b| *b as char).collect())
+            .collect(),
+        _ => {
+            let size = match ty {
+                0x03 | 0x04 => 1,
+                0x05 | 0x06 => 2,
+                0x07 | 0x08 | 0x0b | 0x0d | 0x14 => 4,
+                0x0f | 0x12 => 16,
+                _ => 8,
+            };
+            data.chunks(size)
+                .filter(|c| c.len() == size)
+                .filter_map(|c| format_value(ty, c))
+                .collect()
+        }
+    };
+    Some(items.join(","))
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units = data
+        .chunks(2)
+        .filter(|c| c.len() == 2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').to_string()
+}
+
+fn guid(d: &[u8]) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{}-{}}}",
+        u32::from_le_bytes([d[0], d[1], d[2], d[3]]),
+        u16::from_le_bytes([d[4], d[5]]),
+        u16::from_le_bytes([d[6], d[7]]),
+        d[8..10].iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+        d[10..16].iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+    )
+}
+
+/// FILETIME (100ns ticks since 1601) as RFC3339 with the full 7 digit precision
+pub(crate) fn filetime(ticks: u64) -> Option<String> {
+    let secs = (ticks / 10_000_000) as i64 - FILETIME_EPOCH_DELTA;
+    let dt = Utc.timestamp_opt(secs, 0).single()?;
+    Some(format!("{}.{:07}Z", dt.format("%Y-%m-%dT%H:%M:%S"), ticks % 10_000_000))
+}
+
+fn systemtime(d: &[u8]) -> Option<String> {
+    let field = |i: usize| u16::from_le_bytes([d[i * 2], d[i * 2 + 1]]) as u32;
+    let dt = NaiveDate::from_ymd_opt(field(0) as i32, field(1), field(3))?
+        .and_hms_milli_opt(field(4), field(5), field(6), field(7))?;
+    Some(format!("{}Z", dt.format("%Y-%m-%dT%H:%M:%S%.3f")))
+}
+
+fn sid(d: &[u8]) -> Option<String> {
+    let revision = *d.first()?;
+    let count = *d.get(1)? as usize;
+    let authority = d
+        .get(2..8)?
+        .iter()
+        .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+    let mut s = format!("S-{}-{}", revision, authority);
+    for i in 0..count {
+        let sub = d.get(8 + i * 4..12 + i * 4)?;
+        s.push_str(&format!("-{}", u32::from_le_bytes(sub.try_into().ok()?)));
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_value() {
+        let ticks: u64 = 132_561_334_494_856_969;
+        assert_eq!(
+            format_value(0x11, &ticks.to_le_bytes()).unwrap(),
+            "2021-01-26T11:17:29.4856969Z"
+        );
+        let sid_bytes = [1u8, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+        assert_eq!(format_value(0x13, &sid_bytes).unwrap(), "S-1-5-18");
+        assert_eq!(
+            format_value(0x15, &0x8020_0000_0000_0000u64.to_le_bytes()).unwrap(),
+            "0x8020000000000000"
+        );
+        let g = [
+            0x25, 0x96, 0x84, 0x54, 0x78, 0x54, 0x94, 0x49,
+            0xa5, 0xba, 0x3e, 0x3b, 0x03, 0x28, 0xc3, 0x0d,
+        ];
+        assert_eq!(format_value(0x0f, &g).unwrap(), "{54849625-5478-4994-a5ba-3e3b0328c30d}");
+        let s = "a\0bc\0".encode_utf16().flat_map(|u| u.to_le_bytes()).collect::<Vec<u8>>();
+        assert_eq!(format_value(0x81, &s).unwrap(), "a,bc");
+    }
+}
@@ -0,0 +1,164 @@
+use convert_case::{Case, Casing};
+use serde_json::{Map, Value};
+
+use crate::Event;
+
+/// Case of the flattened keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCase {
+    /// As named in the event (eg: `System.EventRecordID`)
+    Original,
+    /// eg: `system.event_record_id`
+    Snake,
+    /// eg: `system.eventRecordId`
+    Camel,
+}
+
+/// Options of the flat JSON conversion
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, FlatOptions, KeyCase, ToFlatJson};
+///
+/// let opts = FlatOptions { separator: "_".to_string(), case: KeyCase::Snake };
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let flat = e.to_flat_json_with(&opts);
+/// assert_eq!(flat["system_provider_name"], "Microsoft-Windows-Security-Auditing");
+/// assert_eq!(flat["event_data_data_target_user_name"], "SYSTEM");
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlatOptions {
+    /// Separator of the key path segments, `.` by default
+    pub separator: String,
+    /// Case of the key path segments, as they are by default
+    pub case: KeyCase,
+}
+
+impl Default for FlatOptions {
+    fn default() -> Self {
+        Self { separator: ".".to_string(), case: KeyCase::Original }
+    }
+}
+
+pub trait ToFlatJson {
+    /// Converts into a flat JSON object with the default options:
+    /// one `System.Provider.Name` like key per value
+    fn to_flat_json(&self) -> Value {
+        self.to_flat_json_with(&FlatOptions::default())
+    }
+
+    /// Converts into a flat JSON object with the given separator and key case
+    fn to_flat_json_with(&self, opts: &FlatOptions) -> Value;
+}
+
+impl ToFlatJson for Event {
+    fn to_flat_json_with(&self, opts: &FlatOptions) -> Value {
+        //! Converts Windows Event into a flat JSON object.
+        //! Event data always has the same shape: named values under their name
+        //! (the first of duplicates), unnamed ones as `param1`, `param2`, ...
+        let mut evt = serde_json::to_value(self).unwrap();
+        if let (Some(data), Some(event_data)) = (evt.pointer_mut("/EventData/Data"), &self.EventData) {
+            *data = Value::Object(event_data.Data.to_object());
+        }
+
+        let mut flat = Map::new();
+        flatten(&mut flat, None, evt, opts);
+        Value::Object(flat)
+    }
+}
+
+/// Add the leaves of `value` to `flat`, keyed by their path.
+/// A path already taken (eg: `LogonType` and `logon_type` in snake case)
+/// gets the first free numeric suffix: `logon_type_2`, `logon_type_3`, ...
+fn flatten(flat: &mut Map<String, Value>, path: Option<&str>, value: Value, opts: &FlatOptions) {
+    let key = |segment: &str| {
+        let segment = match opts.case {
+            KeyCase::Original => segment.to_string(),
+            KeyCase::Snake => segment.to_case(Case::Snake),
+            KeyCase::Camel => segment.to_case(Case::Camel),
+        };
+        match path {
+            Some(path) => format!("{}{}{}", path, opts.separator, segment),
+            None => segment,
+        }
+    };
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten(flat, Some(&key(&k)), v, opts);
+            }
+        }
+        Value::Array(arr) => {
+            for (i, v) in arr.into_iter().enumerate() {
+                flatten(flat, Some(&key(&i.to_string())), v, opts);
+            }
+        }
+        leaf => {
+            let path = path.unwrap_or_default();
+            let mut key = path.to_string();
+            let mut n = 2;
+            while flat.contains_key(&key) {
+                key = format!("{}_{}", path, n);
+                n += 1;
+            }
+            flat.insert(key, leaf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_flat_json() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let flat = e.to_flat_json();
+        let flat = flat.as_object().unwrap();
+        assert!(flat.values().all(|v| !v.is_object() && !v.is_array()));
+        assert_eq!(flat["System.Provider.Name"], "Microsoft-Windows-Security-Auditing");
+        assert_eq!(flat["System.EventRecordID"], 26893);
+        assert_eq!(flat["System.Event.EventID"], 4624);
+        assert_eq!(flat["EventData.Data.TargetUserName"], "SYSTEM");
+        assert!(flat["System.Provider.EventSourceName"].is_null());
+
+        let opts = FlatOptions { separator: "/".to_string(), case: KeyCase::Camel };
+        let flat = e.to_flat_json_with(&opts);
+        assert_eq!(flat["system/eventRecordId"], 26893);
+        assert_eq!(flat["eventData/data/targetUserName"], "SYSTEM");
+    }
+
+    #[test]
+    fn test_event_data_shape() {
+        // Unnamed values
+        let e = from_file("data/winevt2.xml").unwrap();
+        let flat = e.to_flat_json();
+        assert!(flat.get("EventData.Data").is_none());
+        let e = from_file("data/winevt6.xml").unwrap();
+        let flat = e.to_flat_json();
+        assert!(flat.as_object().unwrap().values().all(|v| !v.is_object() && !v.is_array()));
+        let e = from_file("data/winevt3.xml").unwrap();
+        let flat = e.to_flat_json();
+        assert_eq!(flat["EventData.Data.param1"], "Windows Defender");
+        assert_eq!(flat["EventData.Data.param2"], "SECURITY_PRODUCT_STATE_ON");
+    }
+
+    #[test]
+    fn test_key_collisions() {
+        // Names that only differ by case end up with the same key
+        let xml = std::fs::read_to_string("data/winevt1.xml")
+            .unwrap()
+            .replace("</EventData>", "<Data Name=\"target_user_name\">other</Data></EventData>");
+        let e = crate::from_string(xml).unwrap();
+        let opts = FlatOptions { case: KeyCase::Snake, ..Default::default() };
+        let flat = e.to_flat_json_with(&opts);
+        assert_eq!(flat["event_data.data.target_user_name"], "SYSTEM");
+        assert_eq!(flat["event_data.data.target_user_name_2"], "other");
+
+        let flat = e.to_flat_json();
+        assert_eq!(flat["EventData.Data.TargetUserName"], "SYSTEM");
+        assert_eq!(flat["EventData.Data.target_user_name"], "other");
+    }
+}
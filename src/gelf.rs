@@ -0,0 +1,257 @@
+use std::{
+    io::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    Event,
+    cef::parser,
+    error::{Error, Result},
+    mappers::MappingRegistry,
+    syslog,
+};
+
+/// Magic bytes starting every chunk of a chunked GELF message
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// Size of the chunk header: magic, message id, sequence number and count
+const CHUNK_HEADER_SIZE: usize = 12;
+/// Most chunks a GELF message can be split into
+const MAX_CHUNKS: usize = 128;
+
+/// Compression of the GELF UDP datagrams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GelfCompression {
+    None,
+    Zlib,
+    Gzip,
+}
+
+/// Options of the GELF UDP encoding
+#[derive(Debug, Clone)]
+pub struct GelfUdpOptions {
+    /// Compression of the message, zlib by default
+    pub compression: GelfCompression,
+    /// Largest datagram, 8192 bytes by default (1420 is safer over a WAN).
+    /// Larger messages are chunked
+    pub max_datagram_size: usize,
+}
+
+impl Default for GelfUdpOptions {
+    fn default() -> Self {
+        Self { compression: GelfCompression::Zlib, max_datagram_size: 8192 }
+    }
+}
+
+pub trait ToGELF {
+    /// Converts into a GELF 1.1 message (JSON), as sent over TCP or HTTP
+    fn to_gelf(&self) -> String;
+
+    /// Converts into the UDP datagrams of a GELF 1.1 message,
+    /// compressed and chunked as need be
+    fn to_gelf_udp(&self, opts: &GelfUdpOptions) -> Result<Vec<Vec<u8>>> {
+        let gelf = self.to_gelf().into_bytes();
+        let payload = match opts.compression {
+            GelfCompression::None => gelf,
+            GelfCompression::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&gelf)?;
+                encoder.finish()?
+            }
+            GelfCompression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&gelf)?;
+                encoder.finish()?
+            }
+        };
+        chunk(payload, opts.max_datagram_size)
+    }
+}
+
+impl ToGELF for Event {
+    fn to_gelf(&self) -> String {
+        //! Converts Windows Event into
+        //! a Graylog Extended Log Format (GELF) 1.1 message
+        let system = &self.System;
+        let extension = parser::build_cef_extension(self, MappingRegistry::builtin()).unwrap_or_else(|o| o.extension);
+
+        let mut gelf = Map::new();
+        gelf.insert("version".into(), "1.1".into());
+        gelf.insert("host".into(), system.Computer.as_str().into());
+        gelf.insert("short_message".into(), system.Event.EventName.as_str().into());
+        if let Some(message) = extension.get("msg").filter(|m| !m.is_empty()) {
+            gelf.insert("full_message".into(), message.as_str().into());
+        }
+        gelf.insert("timestamp".into(), (system.TimeCreated.timestamp_millis() as f64 / 1000.0).into());
+        gelf.insert("level".into(), syslog::severity(&system.Level).into());
+
+        let provider = system.Provider.as_ref();
+        let fields: [(&str, Value); 14] = [
+            ("event_id", system.Event.EventID.into()),
+            ("event_record_id", system.EventRecordID.into()),
+            ("channel", system.Channel.as_str().into()),
+            ("provider_name", provider.and_then(|p| p.Name.as_deref()).into()),
+            ("provider_guid", provider.and_then(|p| p.Guid.as_deref()).into()),
+            ("level_name", system.Level.name.as_str().into()),
+            ("task", system.Task.name.as_str().into()),
+            ("opcode", system.Opcode.name.as_str().into()),
+            ("keywords", system.Keywords.to_string().into()),
+            ("version", system.Version.into()),
+            ("process_id", system.Execution.ProcessID.into()),
+            ("thread_id", system.Execution.ThreadID.into()),
+            ("activity_id", system.Correlation.ActivityID.as_deref().into()),
+            ("user_id", system.Security.as_ref().and_then(|s| s.UserID.as_deref()).into()),
+        ];
+        for (name, value) in fields.iter() {
+            add_field(&mut gelf, name, value.clone());
+        }
+
+        // Event data, named or positional (`param1`, ...)
+        if let Some(data) = &self.EventData {
+            for (k, v) in data.Data.to_object() {
+                add_field(&mut gelf, &k, v);
+            }
+        }
+
+        Value::Object(gelf).to_string()
+    }
+}
+
+/// Add an additional field: its name gets the `_` prefix and
+/// only keeps ASCII letters, digits, `_`, `.` and `-`. Missing values are left out,
+/// as are fields named after one already set (or the reserved `_id`)
+fn add_field(gelf: &mut Map<String, Value>, name: &str, value: Value) {
+    if value.is_null() {
+        return;
+    }
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' { c } else { '_' })
+        .collect::<String>();
+    let name = format!("_{}", name);
+    if name != "_id" && !gelf.contains_key(&name) {
+        gelf.insert(name, value);
+    }
+}
+
+/// Split a (compressed) GELF message into datagrams of at most `max_size` bytes
+fn chunk(payload: Vec<u8>, max_size: usize) -> Result<Vec<Vec<u8>>> {
+    if payload.len() <= max_size {
+        return Ok(vec![payload]);
+    }
+    let chunk_size = max_size.saturating_sub(CHUNK_HEADER_SIZE).max(1);
+    let count = payload.len().div_ceil(chunk_size);
+    if count > MAX_CHUNKS || max_size <= CHUNK_HEADER_SIZE {
+        return Err(Error::GelfTooLarge { size: payload.len(), max_datagram_size: max_size });
+    }
+
+    let id = message_id(&payload);
+    Ok(payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(seq, data)| {
+            let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + data.len());
+            datagram.extend_from_slice(&CHUNK_MAGIC);
+            datagram.extend_from_slice(&id);
+            datagram.push(seq as u8);
+            datagram.push(count as u8);
+            datagram.extend_from_slice(data);
+            datagram
+        })
+        .collect())
+}
+
+/// A message id unique to this message: the time, a counter and the payload checksum
+fn message_id(payload: &[u8]) -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let id = nanos ^ (count << 48) ^ (crc32fast::hash(payload) as u64) << 16;
+    id.to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+    use crate::{from_file, ErrorKind};
+
+    #[test]
+    fn test_gelf() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let gelf: Value = serde_json::from_str(&e.to_gelf()).unwrap();
+        assert_eq!(gelf["version"], "1.1");
+        assert_eq!(gelf["host"], "DESKTOP-G089JUF");
+        assert_eq!(gelf["short_message"], "An account was successfully logged on.");
+        assert!(gelf["full_message"].as_str().unwrap().starts_with("This event is generated"));
+        assert_eq!(gelf["timestamp"], 1611659849.485);
+        assert_eq!(gelf["level"], 6);
+        assert_eq!(gelf["_event_id"], 4624);
+        assert_eq!(gelf["_channel"], "Security");
+        assert_eq!(gelf["_keywords"], "Audit Success");
+        assert_eq!(gelf["_TargetUserName"], "SYSTEM");
+        assert!(gelf.get("_user_id").is_none());
+        assert!(gelf.as_object().unwrap().keys().skip(6).all(|k| k.starts_with('_')));
+
+        // Security user id
+        let e = from_file("data/winevt7.xml").unwrap();
+        let gelf: Value = serde_json::from_str(&e.to_gelf()).unwrap();
+        assert_eq!(gelf["_user_id"], "S-1-5-21-4112214325-288924930-761170289-1001");
+        assert_eq!(gelf["_param1"], "C:\\Windows\\System32\\RuntimeBroker.exe (DESKTOP-LAJA961)");
+    }
+
+    #[test]
+    fn test_field_names() {
+        // GELF field names are limited to `[\w\.\-]`, in ASCII
+        let mut gelf = Map::new();
+        add_field(&mut gelf, "Nom d'hôte", Value::from("a"));
+        add_field(&mut gelf, "Benutzer.Größe-1", Value::from("b"));
+        add_field(&mut gelf, "名前", Value::from("c"));
+        assert_eq!(gelf["_Nom_d_h_te"], "a");
+        assert_eq!(gelf["_Benutzer.Gr__e-1"], "b");
+        assert_eq!(gelf["___"], "c");
+    }
+
+    #[test]
+    fn test_gelf_udp() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let gelf = e.to_gelf();
+
+        let opts = GelfUdpOptions { compression: GelfCompression::None, ..Default::default() };
+        assert_eq!(e.to_gelf_udp(&opts).unwrap(), vec![gelf.clone().into_bytes()]);
+
+        // Compressed and chunked
+        let opts = GelfUdpOptions { max_datagram_size: 100, ..Default::default() };
+        let datagrams = e.to_gelf_udp(&opts).unwrap();
+        assert!(datagrams.len() > 1);
+        let mut compressed = Vec::new();
+        for (seq, datagram) in datagrams.iter().enumerate() {
+            assert!(datagram.len() <= 100);
+            assert_eq!(datagram[..2], CHUNK_MAGIC);
+            assert_eq!(datagram[2..10], datagrams[0][2..10]);
+            assert_eq!(datagram[10] as usize, seq);
+            assert_eq!(datagram[11] as usize, datagrams.len());
+            compressed.extend_from_slice(&datagram[CHUNK_HEADER_SIZE..]);
+        }
+        let mut decompressed = String::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, gelf);
+
+        // More than 128 chunks
+        let opts = GelfUdpOptions { max_datagram_size: 13, ..Default::default() };
+        let err = e.to_gelf_udp(&opts).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::GelfTooLarge);
+    }
+}
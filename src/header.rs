@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use crate::{
+    de::{Event, Level},
+    mappers::levels,
+};
+
+/// Where a CEF or LEEF header field takes its value from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderField {
+    /// Fixed text
+    Fixed(String),
+    /// The `System.Computer` of the event
+    Computer,
+    /// The value of a named `<Data>` of the event (eg: an OS build number),
+    /// or the given default if the event has no such Data
+    Data { name: String, default: String },
+}
+
+impl HeaderField {
+    /// Fixed text
+    pub fn fixed<S: Into<String>>(text: S) -> Self {
+        HeaderField::Fixed(text.into())
+    }
+
+    /// Get the value of the field for the given event
+    pub(crate) fn resolve(&self, evt: &Event) -> String {
+        match self {
+            HeaderField::Fixed(text) => text.to_string(),
+            HeaderField::Computer => evt.System.Computer.to_string(),
+            HeaderField::Data { name, default } => evt
+                .EventData
+                .as_ref()
+                .and_then(|d| d.Data.get(name))
+                .filter(|v| !v.is_empty())
+                .unwrap_or(default)
+                .to_string(),
+        }
+    }
+}
+
+/// The default severity of the standard event levels, keyed by level name
+pub(crate) fn default_severities() -> HashMap<String, usize> {
+    levels::LEVELS
+        .iter()
+        .map(|level| (level.to_string(), levels::from_string_to_usize(level)))
+        .collect()
+}
+
+/// Get the severity of an event level: by name, or by the standard name
+/// of its value when localized, else `default`
+pub(crate) fn severity(severities: &HashMap<String, usize>, default: usize, level: &Level) -> usize {
+    severities
+        .get(&level.name)
+        .or_else(|| severities.get(&levels::from_usize_to_string(&level.value.into())))
+        .copied()
+        .unwrap_or(default)
+}
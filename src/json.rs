@@ -0,0 +1,92 @@
+use crate::{
+    time::TimeFormat,
+    Event,
+};
+
+/// Options of the JSON conversion
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, JsonOptions, TimeFormat, ToJson};
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// // `"Data":{"SubjectUserSid":"S-1-5-18",...}`
+/// assert!(e.to_json().contains(r#""SubjectUserSid":"S-1-5-18""#));
+///
+/// // `"Data":[{"Name":"SubjectUserSid","Value":"S-1-5-18"},...]`
+/// let opts = JsonOptions { event_data_items: true, ..Default::default() };
+/// assert!(e.to_json_with(&opts).contains(r#""Name":"SubjectUserSid","Value":"S-1-5-18""#));
+///
+/// // `"TimeCreated":1611659849485`
+/// let opts = JsonOptions { time_format: TimeFormat::EpochMillis, ..Default::default() };
+/// assert!(e.to_json_with(&opts).contains(r#""TimeCreated":1611659849485,"#));
+/// ```
+#[derive(Debug, Clone)]
+pub struct JsonOptions {
+    /// Write `EventData.Data` as an array of `{"Name", "Value"}` items in
+    /// document order, keeping duplicate names and empty unnamed values.
+    /// By default it is an object of the named values, an array of the
+    /// unnamed ones, or both in an array
+    pub event_data_items: bool,
+    /// Indent the JSON
+    pub pretty: bool,
+    /// Format of `System.TimeCreated`, RFC 3339 by default.
+    /// Epoch times are written as numbers
+    pub time_format: TimeFormat,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self { event_data_items: false, pretty: false, time_format: TimeFormat::Rfc3339 }
+    }
+}
+
+pub trait ToJson {
+    /// Converts into JSON with the default options
+    fn to_json(&self) -> String {
+        self.to_json_with(&JsonOptions::default())
+    }
+
+    /// Converts into JSON with the given options
+    fn to_json_with(&self, opts: &JsonOptions) -> String;
+}
+
+impl ToJson for Event {
+    fn to_json_with(&self, opts: &JsonOptions) -> String {
+        let mut evt = serde_json::to_value(self).unwrap();
+        if let (Some(data), Some(event_data), true) =
+            (evt.pointer_mut("/EventData/Data"), &self.EventData, opts.event_data_items)
+        {
+            *data = serde_json::to_value(event_data.Data.iter().collect::<Vec<_>>()).unwrap();
+        }
+        if let Some(time) = evt.pointer_mut("/System/TimeCreated") {
+            *time = opts.time_format.to_json(&self.System.TimeCreated);
+        }
+        if opts.pretty {
+            serde_json::to_string_pretty(&evt).unwrap()
+        } else {
+            evt.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_time_format() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let json = |time_format| {
+            let opts = JsonOptions { time_format, pretty: true, ..Default::default() };
+            serde_json::from_str::<serde_json::Value>(&e.to_json_with(&opts)).unwrap()
+        };
+        assert_eq!(json(TimeFormat::Rfc3339)["System"]["TimeCreated"], "2021-01-26T11:17:29.4856969Z");
+        assert_eq!(json(TimeFormat::EpochSeconds)["System"]["TimeCreated"], 1611659849);
+        assert_eq!(json(TimeFormat::EpochMillis)["System"]["TimeCreated"], 1611659849485_i64);
+        assert_eq!(json(TimeFormat::Cef)["System"]["TimeCreated"], "Jan 26 2021 11:17:29.485");
+        // Serializing the event itself is not affected
+        assert_eq!(serde_json::to_value(&e).unwrap()["System"]["TimeCreated"], "2021-01-26T11:17:29.4856969Z");
+    }
+}
@@ -0,0 +1,208 @@
+pub(crate) mod options;
+
+pub use options::LeefOptions;
+
+use convert_case::{Case, Casing};
+
+use crate::{
+    Event,
+    cef::{parser, utils},
+    mappers::MappingRegistry,
+    time::TimeFormat,
+};
+
+/// CEF extension keys and the QRadar-standard LEEF attribute they become.
+/// The first CEF key found wins when several map to the same attribute.
+const LEEF_KEYS: [(&str, &str); 11] = [
+    ("src", "src"),
+    ("dst", "dst"),
+    ("spt", "srcPort"),
+    ("dpt", "dstPort"),
+    ("smac", "srcMAC"),
+    ("dmac", "dstMAC"),
+    ("proto", "proto"),
+    ("duser", "usrName"),
+    ("suser", "usrName"),
+    ("dntdom", "domain"),
+    ("request", "url"),
+];
+
+/// Format of the `devTime` attribute
+const DEV_TIME_FORMAT: &str = "MMM dd yyyy HH:mm:ss.SSS";
+
+pub trait ToLEEF {
+    /// Converts into LEEF 2.0 with the default options
+    fn to_leef(&self) -> String {
+        self.to_leef_with(&LeefOptions::default())
+    }
+
+    /// Converts into LEEF 2.0 with the given header, delimiter and severity options
+    fn to_leef_with(&self, opts: &LeefOptions) -> String;
+
+    /// Converts into LEEF 2.0, mapping the event data
+    /// with the given registry instead of the built-in one
+    fn to_leef_with_mappings(&self, registry: &MappingRegistry) -> String;
+}
+
+impl ToLEEF for Event {
+    fn to_leef_with(&self, opts: &LeefOptions) -> String {
+        format_leef(self, opts, MappingRegistry::builtin())
+    }
+
+    fn to_leef_with_mappings(&self, registry: &MappingRegistry) -> String {
+        format_leef(self, &LeefOptions::default(), registry)
+    }
+}
+
+fn format_leef(evt: &Event, opts: &LeefOptions, registry: &MappingRegistry) -> String {
+    //! Converts Windows Event into
+    //! Log Event Extended Format (LEEF) 2.0
+
+    // Build the LEEF header
+    let leef_header = format!(
+        "LEEF:2.0|{vendor}|{product}|{product_ver}|{event_id}|{delimiter}|",
+        vendor = utils::escape_header(&opts.vendor.resolve(evt)),
+        product = utils::escape_header(&opts.product.resolve(evt)),
+        product_ver = utils::escape_header(&opts.version.resolve(evt)),
+        event_id = evt.System.Event.EventID,
+        delimiter = opts.header_delimiter(),
+    );
+
+    // Build the LEEF attributes
+    let leef_attributes = build_leef_attributes(evt, opts, registry)
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, escape_value(v, opts.delimiter)))
+        .collect::<Vec<String>>()
+        .join(&opts.delimiter.to_string());
+
+    format!("{}{}", leef_header, leef_attributes)
+}
+
+/// Build the LEEF attributes out of the CEF extension of the event
+fn build_leef_attributes(evt: &Event, opts: &LeefOptions, registry: &MappingRegistry) -> Vec<(String, String)> {
+    let mut extension = parser::build_cef_extension(evt, registry).unwrap_or_else(|o| o.extension);
+    // Replaced by the header and `devTime`
+    extension.remove("external_id");
+    extension.remove("start");
+
+    let mut result = Vec::new();
+    result.push(("devTime".to_string(), TimeFormat::Cef.format(&evt.System.TimeCreated)));
+    result.push(("devTimeFormat".to_string(), DEV_TIME_FORMAT.to_string()));
+    result.push(("sev".to_string(), opts.severity(&evt.System.Level).to_string()));
+
+    let mut attributes = Vec::new();
+    for (cef_key, leef_key) in LEEF_KEYS.iter() {
+        if attributes.iter().any(|(k, _)| k == leef_key) {
+            continue;
+        }
+        if let Some(value) = extension.remove(*cef_key) {
+            attributes.push((leef_key.to_string(), value));
+        }
+    }
+
+    // LEEF has no labels: custom fields are named after theirs,
+    // unless taken by another attribute
+    let labelled = extension
+        .keys()
+        .filter(|k| extension.contains_key(&format!("{}Label", k)))
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in labelled {
+        let label = extension[&format!("{}Label", key)].to_case(Case::Camel);
+        let taken = extension.contains_key(&label)
+            || result.iter().chain(&attributes).any(|(k, _)| *k == label);
+        if label.is_empty() || taken {
+            continue;
+        }
+        extension.remove(&format!("{}Label", key));
+        let value = extension.remove(&key).unwrap_or_default();
+        attributes.push((label, value));
+    }
+
+    attributes.extend(extension);
+    attributes.sort();
+    result.extend(attributes);
+    result
+}
+
+/// Make a value safe to put in a LEEF attribute: the delimiter
+/// and line breaks (which would end the attribute or event) become spaces
+fn escape_value(value: &str, delimiter: char) -> String {
+    value
+        .replace("\r\n", " ")
+        .replace([delimiter, '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::from_file;
+
+    /// Split a LEEF line into its header fields and attributes
+    fn parse_leef(leef: &str, delimiter: char) -> (Vec<&str>, HashMap<&str, &str>) {
+        let fields = leef.splitn(7, '|').collect::<Vec<_>>();
+        let attributes = fields[6]
+            .split(delimiter)
+            .map(|kv| {
+                let mut kv = kv.splitn(2, '=');
+                (kv.next().unwrap(), kv.next().unwrap())
+            })
+            .collect();
+        (fields[..6].to_vec(), attributes)
+    }
+
+    #[test]
+    fn test_leef() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let leef = e.to_leef();
+        let (header, attributes) = parse_leef(&leef, '\t');
+        assert_eq!(header, ["LEEF:2.0", "Microsoft", "Windows", "Windows 7", "4624", "x09"]);
+        assert_eq!(attributes["devTime"], "Jan 26 2021 11:17:29.485");
+        assert_eq!(attributes["devTimeFormat"], DEV_TIME_FORMAT);
+        assert_eq!(attributes["sev"], "2");
+        assert_eq!(attributes["usrName"], "SYSTEM");
+        assert!(attributes["msg"].starts_with("This event is generated when a logon session is created."));
+        assert_eq!(attributes["eventRecordId"], "26893");
+        assert_eq!(attributes["keywords"], "Audit Success");
+        assert!(!attributes.contains_key("duser"));
+        assert!(!attributes.keys().any(|k| k.ends_with("Label")));
+    }
+
+    #[test]
+    fn test_label_collisions() {
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv(
+                "4624,,,,,,Device Custom String 1:ImpersonationLevel (usrName),\
+                 Device Custom String 4:RestrictedAdminMode (sev)",
+            )
+            .unwrap()
+            .build();
+        let e = from_file("data/winevt1.xml").unwrap();
+        let leef = e.to_leef_with_mappings(&registry);
+        let (_, attributes) = parse_leef(&leef, '\t');
+        // Fields named like an attribute keep their custom field key
+        assert_eq!(attributes["usrName"], "SYSTEM");
+        assert_eq!(attributes["sev"], "2");
+        assert_eq!(attributes["cs1Label"], "usrName");
+        assert_eq!(attributes["cs1"], "%%1833");
+        assert_eq!(attributes["cs4Label"], "sev");
+        assert!(attributes.contains_key("cs4"));
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let mut e = from_file("data/winevt1.xml").unwrap();
+        e.System.Keywords.names = vec!["Audit^Success\nbroken".to_string()];
+        let opts = LeefOptions { delimiter: '^', ..Default::default() };
+        let leef = e.to_leef_with(&opts);
+        assert!(!leef.contains('\t') && !leef.contains('\n'));
+        let (header, attributes) = parse_leef(&leef, '^');
+        assert_eq!(header[5], "^");
+        assert_eq!(attributes["keywords"], "Audit Success broken");
+
+        let opts = LeefOptions { delimiter: '|', ..Default::default() };
+        assert!(e.to_leef_with(&opts).contains("|4624|x7C|"));
+    }
+}
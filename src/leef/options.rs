@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::{
+    de::Level,
+    header::{self, HeaderField},
+};
+
+/// Options of the LEEF conversion
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, LeefOptions, ToLEEF};
+///
+/// let opts = LeefOptions { delimiter: '^', ..Default::default() };
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let leef = e.to_leef_with(&opts);
+/// assert!(leef.starts_with("LEEF:2.0|Microsoft|Windows|Windows 7|4624|^|"));
+/// assert!(leef.contains("^usrName=SYSTEM"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LeefOptions {
+    /// Vendor of the header, `Microsoft` by default
+    pub vendor: HeaderField,
+    /// Product of the header, `Windows` by default
+    pub product: HeaderField,
+    /// Product Version of the header, `Windows 7` by default
+    pub version: HeaderField,
+    /// Attribute delimiter, a tab by default
+    pub delimiter: char,
+    /// LEEF severity (1 to 10) of the event levels, keyed by level name (eg: `Error`)
+    pub severity: HashMap<String, usize>,
+    /// Severity of the levels missing from `severity`
+    pub default_severity: usize,
+}
+
+impl Default for LeefOptions {
+    fn default() -> Self {
+        Self {
+            vendor: HeaderField::fixed("Microsoft"),
+            product: HeaderField::fixed("Windows"),
+            version: HeaderField::fixed("Windows 7"),
+            delimiter: '\t',
+            severity: header::default_severities(),
+            default_severity: 1,
+        }
+    }
+}
+
+impl LeefOptions {
+    /// Get the LEEF severity of an event level
+    pub(crate) fn severity(&self, level: &Level) -> usize {
+        header::severity(&self.severity, self.default_severity, level)
+    }
+
+    /// Get the delimiter as written in the header: the character itself,
+    /// or its hex code (eg: `x09` for a tab) if it cannot be written as is
+    pub(crate) fn header_delimiter(&self) -> String {
+        let d = self.delimiter;
+        if d.is_control() || d == '|' || d == ' ' {
+            format!("x{:02X}", d as u32)
+        } else {
+            d.to_string()
+        }
+    }
+}
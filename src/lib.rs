@@ -1,16 +1,58 @@
 mod de;
 use de::Event;
+pub use de::{DataItem, DataList, Keywords, Level, Opcode, RenderingInfo, Task, XmlElement};
+
+mod batch;
+pub use batch::{to_elastic_bulk, to_splunk_hec, BulkAction, BulkOptions, HecOptions, Payload};
 
 mod cef;
-pub use cef::ToCEF;
+pub use cef::{CefOptions, RawEvent, ToCEF};
+
+mod ecs;
+pub use ecs::ToECS;
+
+mod encoding;
+mod error;
+pub use error::{Error, ErrorKind, Location, Result};
+
+mod evtx;
+pub use evtx::EvtxReader;
+
+mod flat;
+pub use flat::{FlatOptions, KeyCase, ToFlatJson};
+
+mod gelf;
+pub use gelf::{GelfCompression, GelfUdpOptions, ToGELF};
+
+mod header;
+pub use header::HeaderField;
+
+mod json;
+pub use json::{JsonOptions, ToJson};
+
+mod leef;
+pub use leef::{LeefOptions, ToLEEF};
 
 mod mappers;
+pub use mappers::{MappingRegistry, MappingRegistryBuilder};
+
+mod ocsf;
+pub use ocsf::ToOCSF;
 
-use quick_xml::de::from_str;
+mod reader;
+pub use reader::EventReader;
+
+mod syslog;
+pub use syslog::{Framing, SyslogFormat, SyslogOptions, ToSyslog};
+
+mod time;
+pub use time::TimeFormat;
+
+use error::Tracked;
 
 use std::{
-    io::Read,
-    fs::File,
+    fs,
+    io::BufRead,
     path::Path,
 };
 
@@ -21,18 +63,8 @@ use std::{
 /// let e = winevents_xml_transform::from_file("data/winevt1.xml").unwrap();
 /// println!("{:#?}", e);
 /// ```
-pub fn from_file<P: AsRef<Path>>(xml_path: P) -> Result<Event, String> {
-    if !Path::new(xml_path.as_ref()).exists() {
-        return Err(format!("{} not found", xml_path.as_ref().to_str().unwrap()))
-    }
-
-    let mut data = String::new();
-    {
-        let mut f = File::open(xml_path).unwrap();
-        f.read_to_string(&mut data).unwrap();
-    }
-
-    from_string(data)
+pub fn from_file<P: AsRef<Path>>(xml_path: P) -> Result<Event> {
+    from_file_with(xml_path, MappingRegistry::builtin())
 }
 
 /// Deserialize Windows Event Log XML from string
@@ -66,20 +98,56 @@ pub fn from_file<P: AsRef<Path>>(xml_path: P) -> Result<Event, String> {
 /// let e = winevents_xml_transform::from_string(xml_string.to_string()).unwrap();
 /// println!("{:#?}", e);
 /// ```
-pub fn from_string(xml_string: String) -> Result<Event, String> {
-    let result: Event = match from_str(&xml_string) {
-        Ok(res) => res,
-        Err(e) => {
-            return Err(format!("{}", e))
-        }
-    };
-
-    Ok(result)
+pub fn from_string(xml_string: String) -> Result<Event> {
+    from_string_with(xml_string, MappingRegistry::builtin())
+}
+
+/// Deserialize Windows Event Log XML from a file, naming events and tasks
+/// with the given mapping registry instead of the built-in one
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file_with, MappingRegistry};
+///
+/// let registry = MappingRegistry::builder()
+///     .tasks_csv("SE_ADT_LOGON_LOGON,12544,Logon (custom)")
+///     .unwrap()
+///     .build();
+/// let e = from_file_with("data/winevt1.xml", &registry).unwrap();
+/// assert_eq!(e.System.Task.name, "Logon (custom)");
+/// ```
+pub fn from_file_with<P: AsRef<Path>>(xml_path: P, registry: &MappingRegistry) -> Result<Event> {
+    let data = fs::read(xml_path.as_ref()).map_err(|e| Error::io(&xml_path, e))?;
+    let data = encoding::decode(&data)?;
+
+    deserialize(data.as_bytes(), registry)
+}
+
+/// Deserialize Windows Event Log XML from string, naming events and tasks
+/// with the given mapping registry instead of the built-in one
+pub fn from_string_with(xml_string: String, registry: &MappingRegistry) -> Result<Event> {
+    encoding::check_declared_encoding(&xml_string)?;
+
+    deserialize(xml_string.as_bytes(), registry)
+}
+
+/// Deserialize one `<Event>` from the reader, keeping track of the position
+/// reached so that errors can point at the offending input. Events and tasks
+/// are named from `registry`.
+fn deserialize<R: BufRead>(reader: R, registry: &MappingRegistry) -> Result<Event> {
+    let reader = Tracked::new(reader);
+    let location = reader.location();
+    let mut de = quick_xml::de::Deserializer::from_reader(reader);
+
+    serde_path_to_error::deserialize(&mut de)
+        .map(|evt| mappers::event_name::name_event(evt, registry))
+        .map(de::Event::apply_rendering_info)
+        .map_err(|e| Error::from_de(e, location.get()))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{from_file, from_string};
+    use super::{from_file, from_string, ErrorKind, JsonOptions, Level, Opcode, Task, TimeFormat, ToCEF, ToJson};
 
     const XML_STRING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
         <Event xmlns="http://schemas.microsoft.com/win/2004/08/events/event">
@@ -123,7 +191,22 @@ mod tests {
         let e = from_string(XML_STRING.to_string());
         let tc = e.unwrap();
         let tc = tc.System.TimeCreated;
-        assert_eq!(tc, "2021-01-26T11:17:29.4856969Z")
+        assert_eq!(tc.timestamp(), 1611659849);
+        // The 100 ns precision is kept
+        assert_eq!(tc.timestamp_subsec_nanos(), 485696900);
+        assert_eq!(TimeFormat::Rfc3339.format(&tc), "2021-01-26T11:17:29.4856969Z");
+
+        let json = serde_json::to_value(from_string(XML_STRING.to_string()).unwrap()).unwrap();
+        assert_eq!(json["System"]["TimeCreated"], "2021-01-26T11:17:29.4856969Z");
+    }
+
+    #[test]
+    fn test_invalid_time_created() {
+        let xml = XML_STRING.replace("2021-01-26T11:17:29.4856969Z", "2021-01-26 11h17");
+        let e = from_string(xml).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Xml);
+        assert_eq!(e.field(), Some("System.TimeCreated"));
+        assert!(e.to_string().contains("invalid timestamp `2021-01-26 11h17`"), "{}", e);
     }
 
     #[test]
@@ -132,4 +215,236 @@ mod tests {
         let xmlns = e.unwrap();
         assert_eq!(xmlns.xmlns, "http://schemas.microsoft.com/win/2004/08/events/event")
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_system_values() {
+        let e = from_string(XML_STRING.to_string()).unwrap();
+        assert_eq!(e.System.Level, Level { value: 0, name: "Information".to_string() });
+        assert_eq!(e.System.Task, Task { value: 12544, name: "Logon".to_string() });
+        assert_eq!(e.System.Opcode, Opcode { value: 0, name: "Info".to_string() });
+        assert_eq!(e.System.Keywords.value, 0x8020_0000_0000_0000);
+
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(json["System"]["Level"], serde_json::json!({"value": 0, "name": "Information"}));
+        assert_eq!(json["System"]["Task"]["value"], 12544);
+
+        // Unknown values are named after themselves
+        let xml = XML_STRING.replace("<Opcode>0<", "<Opcode>77<").replace("<Level>0<", "<Level>16<");
+        let e = from_string(xml).unwrap();
+        assert_eq!(e.System.Opcode, Opcode { value: 77, name: "77".to_string() });
+        assert_eq!(e.System.Level.to_string(), "16");
+
+        let xml = XML_STRING.replace("<Level>0<", "<Level>256<");
+        assert_eq!(from_string(xml).unwrap_err().kind(), ErrorKind::InvalidNumber);
+    }
+
+    #[test]
+    fn test_event_name_by_provider() {
+        let e = from_file("data/winevt5.xml").unwrap();
+        assert_eq!(e.System.Event.EventID, 1151);
+        assert_eq!(e.System.Event.EventName, "Endpoint Protection client health report (time in UTC)");
+        let e = from_file("data/winevt1.xml").unwrap();
+        assert_eq!(e.System.Event.EventName, "An account was successfully logged on.");
+    }
+
+    #[test]
+    fn test_event_data() {
+        let xml = XML_STRING.replace(
+            r#"<Data Name="SubjectDomainName">WORKGROUP</Data>"#,
+            r#"<Data Name="SubjectDomainName">WORKGROUP</Data>
+               <Data Name="PrivilegeList">SeBackupPrivilege</Data>
+               <Data Name="PrivilegeList">SeRestorePrivilege</Data>
+               <Data Name="Unused"/>
+               <Data>positional</Data>
+               <Data/>"#,
+        );
+        let e = from_string(xml).unwrap();
+        let data = &e.EventData.as_ref().unwrap().Data;
+        assert_eq!(data.len(), 8);
+        assert_eq!(data.iter().next().unwrap().Name.as_deref(), Some("SubjectUserSid"));
+        assert_eq!(data.get("SubjectUserName"), Some("DESKTOP-G089JUF$"));
+        assert_eq!(data.get_all("PrivilegeList").collect::<Vec<_>>(), ["SeBackupPrivilege", "SeRestorePrivilege"]);
+        assert_eq!(data.get("Unused"), Some(""));
+        assert_eq!(data.get("Missing"), None);
+        assert_eq!(data.positional(1), Some("S-1-5-18"));
+        assert_eq!(data.positional(7), Some("positional"));
+        assert_eq!(data.positional(8), Some(""));
+        assert_eq!(data.positional(0), None);
+        assert_eq!(data.unnamed().collect::<Vec<_>>(), ["positional", ""]);
+
+        // Shape of older versions, by default
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(json["EventData"]["Data"][0]["PrivilegeList"], "SeRestorePrivilege");
+        assert!(json["EventData"]["Data"][0]["Unused"].is_null());
+        assert_eq!(json["EventData"]["Data"][1], serde_json::json!(["positional"]));
+        let json: serde_json::Value = serde_json::from_str(&e.to_json()).unwrap();
+        assert_eq!(json["EventData"]["Data"][1], serde_json::json!(["positional"]));
+        let e3 = from_file("data/winevt3.xml").unwrap();
+        let json = serde_json::to_value(&e3).unwrap();
+        assert_eq!(json["EventData"]["Data"], serde_json::json!(["Windows Defender", "SECURITY_PRODUCT_STATE_ON"]));
+
+        // Every `<Data>`, on demand
+        let opts = JsonOptions { event_data_items: true, ..Default::default() };
+        let json: serde_json::Value = serde_json::from_str(&e.to_json_with(&opts)).unwrap();
+        assert_eq!(json["EventData"]["Data"][3], serde_json::json!({"Name": "PrivilegeList", "Value": "SeBackupPrivilege"}));
+        assert_eq!(json["EventData"]["Data"][4], serde_json::json!({"Name": "PrivilegeList", "Value": "SeRestorePrivilege"}));
+        assert_eq!(json["EventData"]["Data"][6], serde_json::json!({"Value": "positional"}));
+        assert_eq!(json["EventData"]["Data"][7], serde_json::json!({"Value": ""}));
+    }
+
+    #[test]
+    fn test_user_data() {
+        let xml = XML_STRING.replace(
+            "</EventData>",
+            r#"</EventData>
+            <UserData>
+                <LogFileCleared xmlns="http://manifests.microsoft.com/win/2004/08/windows/eventlog">
+                    <SubjectUserName>alice</SubjectUserName>
+                    <Privilege Enabled="true">SeBackupPrivilege</Privilege>
+                    <Privilege>SeRestorePrivilege</Privilege>
+                    <Client xmlns="http://example.com/client" Version="2"><Name>cli</Name></Client>
+                    <Empty/>
+                </LogFileCleared>
+            </UserData>"#,
+        );
+        let e = from_string(xml).unwrap();
+        let user_data = e.UserData.as_ref().unwrap();
+        assert_eq!(user_data.name, "UserData");
+        let root = user_data.find("LogFileCleared").unwrap();
+        assert_eq!(root.namespace.as_deref(), Some("http://manifests.microsoft.com/win/2004/08/windows/eventlog"));
+        let names = root.children.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["SubjectUserName", "Privilege", "Privilege", "Client", "Empty"]);
+        assert_eq!(root.children[0].namespace, root.namespace);
+        assert_eq!(root.children_named("Privilege").count(), 2);
+        assert_eq!(user_data.value("LogFileCleared/SubjectUserName"), Some("alice"));
+        assert_eq!(user_data.value("LogFileCleared/Privilege"), Some("SeBackupPrivilege"));
+        assert_eq!(user_data.value("LogFileCleared/Privilege/@Enabled"), Some("true"));
+        assert_eq!(user_data.value("LogFileCleared/Client/Name"), Some("cli"));
+        assert_eq!(user_data.value("LogFileCleared/Empty"), Some(""));
+        assert_eq!(user_data.value("LogFileCleared/Missing"), None);
+        let client = root.child("Client").unwrap();
+        assert_eq!(client.namespace.as_deref(), Some("http://example.com/client"));
+        assert_eq!(client.attribute("Version"), Some("2"));
+
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(
+            json["UserData"],
+            serde_json::json!({
+                "LogFileCleared": {
+                    "@xmlns": "http://manifests.microsoft.com/win/2004/08/windows/eventlog",
+                    "SubjectUserName": "alice",
+                    "Privilege": [{"@Enabled": "true", "#text": "SeBackupPrivilege"}, "SeRestorePrivilege"],
+                    "Client": {"@xmlns": "http://example.com/client", "@Version": "2", "Name": "cli"},
+                    "Empty": "",
+                }
+            })
+        );
+        // Deterministic, in document order
+        assert_eq!(serde_json::to_string(&e).unwrap(), serde_json::to_string(&e.clone()).unwrap());
+    }
+
+    #[test]
+    fn test_complex_data() {
+        let xml = XML_STRING.replace(
+            "</EventData>",
+            r#"<ComplexData Name="Rule"><Rule Id="7"><Action>Block</Action></Rule></ComplexData>
+            </EventData>"#,
+        );
+        let e = from_string(xml).unwrap();
+        let data = e.EventData.as_ref().unwrap();
+        assert_eq!(data.Data.len(), 3);
+        assert_eq!(data.ComplexData.len(), 1);
+        let complex = &data.ComplexData[0];
+        assert_eq!(complex.attribute("Name"), Some("Rule"));
+        assert_eq!(complex.value("Rule/@Id"), Some("7"));
+        assert_eq!(complex.value("Rule/Action"), Some("Block"));
+
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(
+            json["EventData"]["ComplexData"],
+            serde_json::json!([{"@Name": "Rule", "Rule": {"@Id": "7", "Action": "Block"}}])
+        );
+        // Left out when there is none
+        let e = from_string(XML_STRING.to_string()).unwrap();
+        assert!(serde_json::to_value(&e).unwrap()["EventData"].get("ComplexData").is_none());
+    }
+
+    #[test]
+    fn test_rendering_info() {
+        let e = from_file("data/winevt10.xml").unwrap();
+        let info = e.RenderingInfo.as_ref().unwrap();
+        assert_eq!(info.Culture.as_deref(), Some("en-US"));
+        assert!(info.Message.as_deref().unwrap().starts_with("An account failed to log on.\n"));
+        assert_eq!(info.Provider.as_deref(), Some("Microsoft Windows security auditing."));
+        assert_eq!(info.Keywords, ["Audit Failure"]);
+
+        // Localized strings over the lookups
+        let xml = XML_STRING.replace(
+            "</EventData>",
+            r#"</EventData>
+            <RenderingInfo Culture="fr-FR">
+                <Message>L’ouverture de session d’un compte s’est correctement déroulée.</Message>
+                <Level>Informations</Level>
+                <Task>Ouvrir la session</Task>
+                <Opcode></Opcode>
+                <Keywords><Keyword>Succès de l’audit</Keyword><Keyword>Classique</Keyword></Keywords>
+            </RenderingInfo>"#,
+        );
+        let e = from_string(xml).unwrap();
+        assert_eq!(e.System.Level.name, "Informations");
+        // Numeric values are kept, so the severity is that of the level
+        assert_eq!(e.System.Level.value, 0);
+        assert_eq!(e.to_cef().split('|').nth(6), Some("2"));
+        assert_eq!(e.System.Task.name, "Ouvrir la session");
+        assert_eq!(e.System.Opcode.name, "Info");
+        assert_eq!(e.System.Keywords.to_string(), "Succès de l’audit, Classique");
+        // The mask is kept, and still tells the outcome
+        assert_eq!(e.System.Keywords.value, 0x8020_0000_0000_0000);
+        assert!(e.System.Keywords.is_audit_success());
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(json["RenderingInfo"]["Keywords"], serde_json::json!(["Succès de l’audit", "Classique"]));
+
+        // Left out when there is none
+        let e = from_string(XML_STRING.to_string()).unwrap();
+        assert!(e.RenderingInfo.is_none());
+        assert_eq!(e.System.Task.name, "Logon");
+    }
+
+    #[test]
+    fn test_from_file_not_found() {
+        let e = from_file("data/does-not-exist.xml").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let xml = XML_STRING.replace("<EventRecordID>26893</EventRecordID>", "");
+        let e = from_string(xml).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingField);
+        assert_eq!(e.field(), Some("System.EventRecordID"));
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        let xml = XML_STRING.replace("<EventRecordID>26893<", "<EventRecordID>abc<");
+        let e = from_string(xml).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidNumber);
+        assert_eq!(e.field(), Some("System.EventRecordID"));
+        assert_eq!(e.location().unwrap().line, 12);
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        let xml = XML_STRING.replace("0x8020000000000000", "Audit Success");
+        let e = from_string(xml).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Xml);
+        assert_eq!(e.field(), Some("System.Keywords"));
+    }
+
+    #[test]
+    fn test_xml_syntax() {
+        let xml = XML_STRING.replace("</System>", "</Sys>");
+        let e = from_string(xml).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Xml);
+    }
+}
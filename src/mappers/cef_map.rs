@@ -2,6 +2,9 @@ use std::collections::HashMap;
 
 use convert_case::{Case, Casing};
 
+use crate::cef::parser::CustomSlot;
+
+#[derive(Clone)]
 pub(crate) struct CefMap {
     pub(crate) map: HashMap<String, Vec<String>>,
     // Reciprocal of `map`
@@ -26,7 +29,7 @@ impl CefMap {
 
         for line in lines.into_iter() {
             let components: Vec<String> = line.split(',').map(|x| x.to_string()).collect();
-            map.insert(components[0].clone(), (&components[1..]).to_vec());
+            map.insert(components[0].clone(), components[1..].to_vec());
         }
         map
     }
@@ -55,6 +58,20 @@ impl CefMap {
         Self { map, inverted_map }
     }
 
+    pub(crate) fn insert(&mut self, cef_key: &str, names: &[String]) {
+        //! Add or replace a CEF field, `names[0]` being the field name
+        //! looked up by `get_cef_field`
+        //!
+        //! ## Example usage
+        //! ```ignore
+        //! cef_map.insert("cs1", &["Logon Type".to_string()]);
+        //! ```
+        if let Some(name) = names.first() {
+            self.inverted_map.insert(Self::get_cef_value_hash(name.to_string()), cef_key.to_string());
+        }
+        self.map.insert(cef_key.to_string(), names.to_vec());
+    }
+
     pub(crate) fn get_cef_field(&self, field: &str) -> Option<String> {
         //! Get the CEF field name by passing any corresponding field value
         //!
@@ -85,6 +102,17 @@ impl CefMap {
             field.to_case(Case::Camel)
         }
     }
+
+    pub(crate) fn is_known(&self, field: &str) -> bool {
+        //! Whether an event mapping field names a CEF field, custom
+        //! slots past the last one included (moved to a free one)
+        //!
+        //! ## Example usage
+        //! ```ignore
+        //! assert!(cef_map.is_known("Device Custom Number 6"));
+        //! ```
+        self.get_cef_field(field).is_some() || CustomSlot::of_key(&field.to_case(Case::Camel)).is_some()
+    }
 }
 
 #[cfg(test)]
@@ -1,14 +1,12 @@
 use serde::{Deserialize, Deserializer};
 
 use crate::{
-    de::EventInfo,
+    de::{Event, EventInfo},
     mappers::*,
 };
 
-fn get_event_name_mapping(event_id: usize) -> String {
-    let map = load_mapping();
-
-    if let Some(name) = map.get_name(&event_id) {
+fn get_event_name_mapping(registry: &MappingRegistry, source: &EventSource, event_id: usize) -> String {
+    if let Some(name) = registry.events.get_name(source, event_id) {
        name.to_string()
     } else {
         format!("EventID-{}", event_id)
@@ -19,24 +17,54 @@ pub(crate) fn eventid_map<'de, D>(deserializer: D) -> Result<EventInfo, D::Error
     where
         D: Deserializer<'de>,
 {
+    //! The name depends on the provider and channel which may come after
+    //! the `EventID` element: it is filled in by `name_event` once the
+    //! whole event is deserialized
     usize::deserialize(deserializer).map(|x| {
-        // i used a match, you can use a HashMap with lazy_static
         EventInfo {
             EventID: x,
-            EventName: get_event_name_mapping(x),
+            EventName: String::new(),
         }
     })
 }
 
+pub(crate) fn name_event(mut evt: Event, registry: &MappingRegistry) -> Event {
+    //! Name the event from its provider, channel and Event Id, and its task
+    //! from its Task Id
+    evt.System.Event.EventName = get_event_name_mapping(registry, &evt.System.source(), evt.System.Event.EventID);
+    tasks::name_task(&mut evt.System.Task, registry);
+    evt
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_events_map() {
-        let evt = get_event_name_mapping(4781);
+        let registry = MappingRegistry::builtin();
+        let security = EventSource { channel: Some("Security"), ..Default::default() };
+        let evt = get_event_name_mapping(registry, &security, 4781);
         assert_eq!(evt, "The name of an account was changed:");
-        let evt = get_event_name_mapping(1);
+        let evt = get_event_name_mapping(registry, &security, 1);
         assert_eq!(evt, "EventID-1")
     }
+
+    #[test]
+    fn test_events_map_by_provider() {
+        let registry = MappingRegistry::builtin();
+        // Security event 4624 from another channel still maps by its provider
+        let forwarded = EventSource {
+            provider: Some("Microsoft-Windows-Security-Auditing"),
+            channel: Some("ForwardedEvents"),
+            ..Default::default()
+        };
+        assert_eq!(get_event_name_mapping(registry, &forwarded, 4624), "An account was successfully logged on.");
+        let guid = EventSource { guid: Some("{54849625-5478-4994-A5BA-3E3B0328C30D}"), ..Default::default() };
+        assert_eq!(get_event_name_mapping(registry, &guid, 4624), "An account was successfully logged on.");
+
+        // Third-party provider logging its own event 4624
+        let other = EventSource { provider: Some("Contoso"), channel: Some("Application"), ..Default::default() };
+        assert_eq!(get_event_name_mapping(registry, &other, 4624), "EventID-4624");
+    }
 }
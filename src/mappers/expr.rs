@@ -0,0 +1,420 @@
+//! The mapping mini-language of `event_mappings.csv`.
+//!
+//! A mapping value is an expression over the `<Data>` of an event:
+//!
+//! - `Name`: value of `<Data Name="Name">` (names may hold spaces)
+//! - `UserData/Root/Child`: text of a UserData element, by path
+//!   (`UserData/Root/@name` for an attribute)
+//! - `%N`: value of the N-th parameter, `<Data Name="paramN">`
+//! - `"text"`: literal text
+//! - `Name (Description)`: a described value, the description labels custom
+//!   CEF fields; values described as an `IPv6 Address` are kept only if they are one
+//! - `one_of(a, b, ...)`: first non-empty value
+//! - `all_of(a, b, ...)`: non-empty values joined with spaces
+//! - `both(a, b)`: values joined with spaces
+//! - `concatenate(a, b, ...)`: values joined together
+//!
+//! `Message` and `Device Action` mappings are free text, unless they start
+//! with a function call. A message can also be `all_of_data`: every Data as
+//! `Name: value` lines.
+use std::{collections::HashMap, fmt};
+
+/// Error raised on a malformed mapping expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the offending token in the expression
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.offset + 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Function {
+    OneOf,
+    AllOf,
+    Both,
+    Concatenate,
+}
+
+impl Function {
+    const ALL: [Function; 4] = [Function::OneOf, Function::AllOf, Function::Both, Function::Concatenate];
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::OneOf => "one_of",
+            Function::AllOf => "all_of",
+            Function::Both => "both",
+            Function::Concatenate => "concatenate",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|f| f.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expr {
+    /// Literal text
+    Literal(String),
+    /// Value of a named Data
+    Data(String),
+    /// Value of the `paramN` Data
+    Param(usize),
+    /// Value with a description
+    Described(Box<Expr>, String),
+    /// Function call
+    Call(Function, Vec<Expr>),
+    /// Every Data as `Name: value` lines
+    AllOfData,
+}
+
+/// A parsed mapping value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    source: String,
+    expr: Expr,
+}
+
+impl Mapping {
+    /// Parse the mapping of the given field
+    pub(crate) fn parse(field: &str, source: &str) -> Result<Self, ParseError> {
+        let text = source.trim();
+        let is_message = field.eq_ignore_ascii_case("message");
+        let is_action = field.eq_ignore_ascii_case("device action");
+        let starts_with_call = Function::ALL
+            .iter()
+            .any(|f| text.strip_prefix(f.name()).is_some_and(|rest| rest.starts_with('(')));
+
+        let expr = if text.is_empty() {
+            Expr::Literal(String::new())
+        } else if is_message && text == "all_of_data" {
+            Expr::AllOfData
+        } else if (is_message || is_action) && !starts_with_call && (is_message || text.contains(' ')) {
+            Expr::Literal(text.to_string())
+        } else {
+            Parser::new(text)?.parse()?
+        };
+        Ok(Self { source: text.to_string(), expr })
+    }
+
+    /// Evaluate the mapping over the Data of an event
+    pub(crate) fn eval(&self, event_data: &HashMap<String, String>) -> String {
+        eval(&self.expr, event_data)
+    }
+
+    /// Label of a custom CEF field holding this value:
+    /// its description or Data name (the last segment of a path), else the mapping itself
+    pub(crate) fn label(&self) -> String {
+        match &self.expr {
+            Expr::Described(_, description) => description.to_string(),
+            Expr::Data(name) => name.rsplit('/').next().unwrap_or(name).trim_start_matches('@').to_string(),
+            _ => self.source.replace('"', ""),
+        }
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn eval(expr: &Expr, event_data: &HashMap<String, String>) -> String {
+    let lookup = |name: &str| event_data.get(name).map(|v| v.trim().to_string()).unwrap_or_default();
+    match expr {
+        Expr::Literal(s) => s.to_string(),
+        Expr::Data(name) => lookup(name),
+        Expr::Param(n) => lookup(&format!("param{}", n)),
+        Expr::Described(e, description) => {
+            let v = eval(e, event_data);
+            if description.contains("IPv6 Address") && !v.contains(':') {
+                String::new()
+            } else {
+                v
+            }
+        }
+        Expr::Call(f, args) => {
+            let values = args.iter().map(|a| eval(a, event_data));
+            let is_set = |v: &String| !v.is_empty() && v != "-";
+            match f {
+                Function::OneOf => values.into_iter().find(is_set).unwrap_or_default(),
+                Function::AllOf => values.filter(is_set).collect::<Vec<_>>().join(" "),
+                Function::Both => values.collect::<Vec<_>>().join(" ").trim().to_string(),
+                Function::Concatenate => values.collect::<Vec<_>>().join(""),
+            }
+        }
+        Expr::AllOfData => {
+            let mut lines = event_data
+                .iter()
+                .filter(|(_k, v)| !v.is_empty())
+                .map(|(k, v)| format!("{}: {}", k, v))
+                .collect::<Vec<String>>();
+            lines.sort();
+            lines.join("\n").replace('"', "")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Param(usize),
+    Quoted(String),
+    Text(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Param(n) => write!(f, "`%{}`", n),
+            Token::Quoted(s) => write!(f, "\"{}\"", s),
+            Token::Text(s) => write!(f, "`{}`", s),
+        }
+    }
+}
+
+/// Token with its start and end byte offsets
+type Spanned = (usize, usize, Token);
+
+fn tokenize(text: &str) -> Result<Vec<Spanned>, ParseError> {
+    let error = |offset, message: &str| ParseError { offset, message: message.to_string() };
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '%' => {
+                let mut digits = String::new();
+                while let Some((_, d)) = chars.peek().filter(|(_, d)| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                Token::Param(digits.parse().map_err(|_| error(start, "expected a parameter number after `%`"))?)
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => s.push(c),
+                        None => return Err(error(start, "unterminated string")),
+                    }
+                }
+                Token::Quoted(s)
+            }
+            c if c.is_whitespace() => continue,
+            ':' => return Err(error(start, "unexpected `:`")),
+            _ => {
+                let mut s = c.to_string();
+                while let Some((_, c)) = chars.peek().filter(|(_, c)| !"(),\"%:".contains(*c)) {
+                    s.push(*c);
+                    chars.next();
+                }
+                let trimmed = s.trim_end();
+                tokens.push((start, start + trimmed.len(), Token::Text(trimmed.to_string())));
+                continue;
+            }
+        };
+        let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+        tokens.push((start, end, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self, ParseError> {
+        Ok(Self { tokens: tokenize(text)?, pos: 0, len: text.len() })
+    }
+
+    fn parse(mut self) -> Result<Expr, ParseError> {
+        let expr = self.expr()?;
+        match self.tokens.get(self.pos) {
+            Some((start, _, token)) => Err(self.error(*start, format!("unexpected {}", token))),
+            None => Ok(expr),
+        }
+    }
+
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        ParseError { offset, message }
+    }
+
+    fn next(&mut self) -> Result<Spanned, ParseError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| self.error(self.len, "unexpected end of mapping".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let (start, end, token) = self.next()?;
+        match token {
+            Token::Quoted(s) => Ok(Expr::Literal(s)),
+            Token::Param(n) => self.described(Expr::Param(n)),
+            // A name directly followed by `(` is a call, `Name (Description)` a description
+            Token::Text(name) => match self.peek() {
+                Some((open, _, Token::Open)) if *open == end => {
+                    let f = Function::from_name(&name)
+                        .ok_or_else(|| self.error(start, format!("unknown function `{}`", name)))?;
+                    self.pos += 1;
+                    self.call(f, start)
+                }
+                _ => self.described(Expr::Data(name)),
+            },
+            token => Err(self.error(start, format!("expected a value, found {}", token))),
+        }
+    }
+
+    fn described(&mut self, expr: Expr) -> Result<Expr, ParseError> {
+        if !matches!(self.peek(), Some((_, _, Token::Open))) {
+            return Ok(expr);
+        }
+        self.pos += 1;
+        let description = match self.next()? {
+            (_, _, Token::Text(s)) | (_, _, Token::Quoted(s)) => s,
+            (start, _, token) => return Err(self.error(start, format!("expected a description, found {}", token))),
+        };
+        match self.next()? {
+            (_, _, Token::Close) => Ok(Expr::Described(Box::new(expr), description)),
+            (start, _, token) => Err(self.error(start, format!("expected `)`, found {}", token))),
+        }
+    }
+
+    fn call(&mut self, f: Function, start: usize) -> Result<Expr, ParseError> {
+        if let Some((_, _, Token::Close)) = self.peek() {
+            return Err(self.error(start, format!("`{}` expects at least one argument", f.name())));
+        }
+        let mut args = vec![self.expr()?];
+        loop {
+            match self.next()? {
+                (_, _, Token::Comma) => args.push(self.expr()?),
+                (_, _, Token::Close) => return Ok(Expr::Call(f, args)),
+                (start, _, token) => {
+                    return Err(self.error(start, format!("expected `,` or `)`, found {}", token)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::ReaderBuilder;
+
+    fn data() -> HashMap<String, String> {
+        [
+            ("param1", "C:\\Windows\\explorer.exe"),
+            ("param5", "restart"),
+            ("TargetDomainName", "CORP"),
+            ("TargetUserName", "alice"),
+            ("SubjectUserSid", "S-1-5-18"),
+            ("IpAddress", "10.0.0.1"),
+            ("Product Name", "Defender"),
+        ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn eval(field: &str, source: &str) -> String {
+        Mapping::parse(field, source).unwrap().eval(&data())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval("Source User Name", "TargetUserName"), "alice");
+        assert_eq!(eval("Product", "Product Name"), "Defender");
+        assert_eq!(eval("Source Process Name", "%1"), "C:\\Windows\\explorer.exe");
+        assert_eq!(eval("Source User Name", "one_of(SubjectUserName, SubjectUserSid)"), "S-1-5-18");
+        assert_eq!(eval("Source Address", r#"one_of(Missing,"localhost")"#), "localhost");
+        assert_eq!(eval("Reason", "all_of(TargetDomainName, Missing,TargetUserName)"), "CORP alice");
+        assert_eq!(eval("Reason", "both(TargetDomainName, TargetUserName)"), "CORP alice");
+        assert_eq!(
+            eval("Message", r#"concatenate("The process ",%1," initiated a ",%5)"#),
+            "The process C:\\Windows\\explorer.exe initiated a restart"
+        );
+        assert_eq!(eval("Destination User Name", r#"concatenate(TargetDomainName,"\",TargetUserName)"#), "CORP\\alice");
+        // Commas and parentheses inside literals, nested calls
+        assert_eq!(
+            eval("Message", r#"all_of(Missing,"Changes (old, new)",one_of(Missing,both(%5, TargetUserName)))"#),
+            "Changes (old, new) restart alice"
+        );
+    }
+
+    #[test]
+    fn test_described() {
+        let m = Mapping::parse("Device Custom IPv6 Address 2", "IpAddress (Source IPv6 Address)").unwrap();
+        assert_eq!(m.eval(&data()), "");
+        assert_eq!(m.label(), "Source IPv6 Address");
+        let m = Mapping::parse("Device Custom String 1", r#"TargetUserName ("Target User")"#).unwrap();
+        assert_eq!(m.eval(&data()), "alice");
+        assert_eq!(m.label(), "Target User");
+        assert_eq!(Mapping::parse("Device Custom String 2", "TargetUserName").unwrap().label(), "TargetUserName");
+    }
+
+    #[test]
+    fn test_text_fields() {
+        assert_eq!(eval("Message", "A token right was adjusted."), "A token right was adjusted.");
+        assert_eq!(eval("Message", "TargetUserName"), "TargetUserName");
+        assert_eq!(eval("Device Action", "No action is necessary."), "No action is necessary.");
+        assert_eq!(eval("Device Action", "TargetUserName"), "alice");
+        assert!(eval("Message", "all_of_data").contains("TargetUserName: alice"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |source| Mapping::parse("Source User Name", source).unwrap_err();
+        assert_eq!(err("one_of(A, B").to_string(), "unexpected end of mapping at column 12");
+        assert_eq!(err("some_of(A)").to_string(), "unknown function `some_of` at column 1");
+        assert_eq!(err(r#"all_of(A, "B)"#).to_string(), "unterminated string at column 11");
+        assert_eq!(err("one_of()").to_string(), "`one_of` expects at least one argument at column 1");
+        assert_eq!(err("A B)").to_string(), "unexpected `)` at column 4");
+        assert_eq!(err("DomainNameSource User ID:LogonId").offset, 24);
+        assert_eq!(err("%x").to_string(), "expected a parameter number after `%` at column 1");
+    }
+
+    #[test]
+    fn test_embedded_mappings() {
+        //! Every mapping of the built-in event mappings must parse
+        let csv = include_str!("../../assets/event_mappings.csv");
+        let mut errors = Vec::new();
+        let records = ReaderBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_reader(csv.as_bytes())
+            .into_records();
+        for (row, record) in records.enumerate() {
+            if let Err(e) = super::super::parse_mapping_row(&record.unwrap()) {
+                errors.push(format!("row {}: {}", row + 1, e));
+            }
+        }
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    }
+}
@@ -1,16 +1,80 @@
-use serde::{Deserialize, Deserializer};
+use serde::{de::Error, Deserialize, Deserializer};
 
-pub(crate) fn keywords_map<'de, D>(deserializer: D) -> Result<String, D::Error>
+use crate::de::Keywords;
+
+/// Names of the standard keywords, reserved bits 48 to 55 (see `winmeta.xml`).
+/// Bits 56 to 63 are those of the channels, 0 to 47 are defined by the providers
+const STANDARD_KEYWORDS: [(u64, &str); 8] = [
+    (0x0001_0000_0000_0000, "Response Time"),
+    (0x0002_0000_0000_0000, "WDI Context"),
+    (0x0004_0000_0000_0000, "WDI Diag"),
+    (0x0008_0000_0000_0000, "SQM"),
+    (Keywords::AUDIT_FAILURE, "Audit Failure"),
+    (Keywords::AUDIT_SUCCESS, "Audit Success"),
+    (0x0040_0000_0000_0000, "Correlation Hint"),
+    // Event log classic (`win:EventlogClassic`)
+    (0x0080_0000_0000_0000, "Classic"),
+];
+
+/// Bits of the keywords defined by the providers
+const PROVIDER_KEYWORDS: u64 = 0x0000_ffff_ffff_ffff;
+
+pub(crate) fn keywords_map<'de, D>(deserializer: D) -> Result<Keywords, D::Error>
     where
         D: Deserializer<'de>,
 {
-    String::deserialize(deserializer).map(|x| {
-        match x.as_str() {
-            "0x8020000000000000" => "Audit Success",
-            "0x8010000000000000" => "Audit Failure",
-            "0x80000000000000" => "Classic",
-            _ => &x
-        }
-            .into()
-    })
+    let mask = String::deserialize(deserializer)?;
+    parse_mask(&mask)
+        .map(Keywords::from_value)
+        .ok_or_else(|| D::Error::custom(format!("invalid keywords mask `{}`", mask.trim())))
+}
+
+/// Parse a keywords mask, in hex (`0x8020000000000000`) or decimal
+fn parse_mask(mask: &str) -> Option<u64> {
+    let mask = mask.trim();
+    match mask.strip_prefix("0x").or_else(|| mask.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => mask.parse().ok(),
+    }
+}
+
+/// Get the names of the flags of a keywords mask: the standard ones,
+/// then the provider ones as their hex mask (eg: `0x10`)
+pub(crate) fn keyword_names(value: u64) -> Vec<String> {
+    let standard = STANDARD_KEYWORDS
+        .iter()
+        .filter(|(mask, _)| value & mask != 0)
+        .map(|(_, name)| name.to_string());
+    let provider = (0..48)
+        .map(|bit| 1u64 << bit)
+        .filter(|mask| value & PROVIDER_KEYWORDS & mask != 0)
+        .map(|mask| format!("0x{:x}", mask));
+    standard.chain(provider).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keywords() {
+        let k = Keywords::from_value(parse_mask("0x8020000000000000").unwrap());
+        assert_eq!(k.value, 0x8020_0000_0000_0000);
+        assert_eq!(k.names, ["Audit Success"]);
+        assert!(k.is_audit_success() && !k.is_audit_failure());
+
+        let k = Keywords::from_value(parse_mask("0x80D0000000000012").unwrap());
+        assert_eq!(k.names, ["Audit Failure", "Correlation Hint", "Classic", "0x2", "0x10"]);
+        assert_eq!(k.to_string(), "Audit Failure, Correlation Hint, Classic, 0x2, 0x10");
+        assert!(k.contains(0x12) && !k.contains(0x13));
+
+        // Channel bits only
+        let k = Keywords::from_value(parse_mask("0x8000000000000000").unwrap());
+        assert!(k.names.is_empty());
+        assert_eq!(k.to_string(), "0x8000000000000000");
+
+        assert_eq!(parse_mask("9007199254740992"), Some(0x20_0000_0000_0000));
+        assert_eq!(parse_mask("0x"), None);
+        assert_eq!(parse_mask("Audit Success"), None);
+    }
 }
@@ -1,11 +1,14 @@
 use serde::{Deserialize, Deserializer};
 
-pub(crate) fn level_map<'de, D>(deserializer: D) -> Result<String, D::Error>
+use crate::de::Level;
+
+pub(crate) fn level_map<'de, D>(deserializer: D) -> Result<Level, D::Error>
     where
         D: Deserializer<'de>,
 {
-    usize::deserialize(deserializer).map(|x| {
-        from_usize_to_string(&x)
+    u8::deserialize(deserializer).map(|x| Level {
+        value: x,
+        name: from_usize_to_string(&x.into()),
     })
 }
 
@@ -22,8 +25,11 @@ pub(crate) fn from_usize_to_string(level: &usize) -> String {
         .into()
 }
 
-pub(crate) fn from_string_to_usize(level: &String) -> usize {
-    match level.as_str() {
+/// Names of the standard event levels
+pub(crate) const LEVELS: [&str; 5] = ["Information", "Critical", "Error", "Warning", "Verbose"];
+
+pub(crate) fn from_string_to_usize(level: &str) -> usize {
+    match level {
         "Information" => 2,
         "Critical" => 10,
         "Error" => 7,
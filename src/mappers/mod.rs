@@ -1,18 +1,21 @@
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, StringRecord};
 
 #[allow(dead_code)]
 pub(crate) mod cef_map;
 pub(crate) mod levels;
 pub(crate) mod keywords;
 pub(crate) mod event_name;
+pub(crate) mod expr;
 pub(crate) mod opcode;
+pub(crate) mod registry;
 pub(crate) mod tasks;
 
-type EventMapping = HashMap<usize, WinEvent>;
+pub use registry::{MappingRegistry, MappingRegistryBuilder};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) use expr::{Mapping, ParseError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WinEvent {
     /// Event Id
     event_id: usize,
@@ -27,118 +30,317 @@ pub struct WinEvent {
     /// Name of the Event
     name: String,
     /// Mapping information of the Event
-    mapping_info: Option<HashMap<String, String>>
+    mapping_info: Option<HashMap<String, Mapping>>
+}
+
+/// Providers logging the events of the channels named in `event_mappings.csv`,
+/// so that events read from another channel (eg: `ForwardedEvents`) still map.
+const CHANNEL_PROVIDERS: &[(&str, &[&str])] = &[
+    ("Security", &["Microsoft-Windows-Security-Auditing", "54849625-5478-4994-a5ba-3e3b0328c30d"]),
+    ("System", &["Microsoft-Windows-Eventlog", "fc65ddd8-d6ef-4962-83d5-6e5cfe9ce148"]),
+    (
+        "Microsoft-Windows-Windows Defender/Operational",
+        &["Microsoft-Windows-Windows Defender", "11cd958a-c507-4ef3-b3f2-5fd9dfbd2c78"],
+    ),
+];
+
+/// Where an event comes from: picks among the mappings sharing its Event Id
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventSource<'a> {
+    /// `System.Provider.Name`
+    pub provider: Option<&'a str>,
+    /// `System.Provider.Guid`
+    pub guid: Option<&'a str>,
+    /// `System.Channel`
+    pub channel: Option<&'a str>,
+}
+
+/// Event mappings keyed by Event Id and source (provider name, provider GUID
+/// or channel), plus the mappings that apply to an Event Id from any source.
+///
+/// Sources are compared ignoring ASCII case, so that looking an event up
+/// does not allocate: only a handful of sources share an Event Id.
+#[derive(Debug, Clone, Default)]
+pub struct EventMapping {
+    by_source: HashMap<usize, Vec<(String, WinEvent)>>,
+    by_id: HashMap<usize, WinEvent>,
+    /// Channel known to be logged to by a provider name/GUID
+    provider_channels: Vec<(String, String)>,
+}
+
+/// Strip a provider name, provider GUID or channel down to its lookup key
+fn source_key(source: &str) -> &str {
+    source.trim().trim_start_matches('{').trim_end_matches('}')
+}
+
+/// Find the value of `key` among source-keyed pairs
+fn find_source<'a, T>(pairs: &'a [(String, T)], key: &str) -> Option<&'a T> {
+    pairs.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v)
+}
+
+impl EventMapping {
+    /// Insert (or replace) the mapping of an event, keyed by its channel
+    /// column, or by its Event Id only if the channel is blank or `-`
+    pub(crate) fn insert(&mut self, e: WinEvent) {
+        match e.channel.as_str() {
+            "" | "-" => {
+                self.by_id.insert(e.event_id, e);
+            }
+            channel => {
+                let key = source_key(channel);
+                let entries = self.by_source.entry(e.event_id).or_default();
+                match entries.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                    Some((_, existing)) => *existing = e,
+                    None => entries.push((key.to_string(), e)),
+                }
+            }
+        }
+    }
+
+    /// All the mappings of an Event Id, whatever their source
+    pub(crate) fn entries_mut(&mut self, event_id: usize) -> impl Iterator<Item = &mut WinEvent> {
+        self.by_source
+            .get_mut(&event_id)
+            .into_iter()
+            .flatten()
+            .map(|(_, e)| e)
+            .chain(self.by_id.get_mut(&event_id))
+    }
+
+    /// Get the mapping of an event given with its source, if any
+    pub(crate) fn get_mut(&mut self, source: &str, event_id: usize) -> Option<&mut WinEvent> {
+        let key = source_key(source);
+        self.by_source
+            .get_mut(&event_id)?
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, e)| e)
+    }
+
+    /// Number of mappings held
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.by_source.values().map(Vec::len).sum::<usize>() + self.by_id.len()
+    }
+}
+
+/// A row of `event_mappings.csv`:
+/// `event_id,category,sub_category,outcome,channel,name,Field:value,...`
+pub(crate) struct MappingRow {
+    pub(crate) event_id: usize,
+    /// Category, sub category, outcome, channel and name, `None` when blank
+    pub(crate) category: Option<String>,
+    pub(crate) sub_category: Option<String>,
+    pub(crate) outcome: Option<String>,
+    pub(crate) channel: Option<String>,
+    pub(crate) name: Option<String>,
+    /// Mapping of each field, `None` when the field is given without a value
+    pub(crate) mapping: Vec<(String, Option<Mapping>)>,
+}
+
+/// Parse a row of `event_mappings.csv`, columns being taken by position.
+/// Errors on a mapping column are prefixed with the column.
+pub(crate) fn parse_mapping_row(record: &StringRecord) -> Result<MappingRow, ParseError> {
+    let mut fields = record.iter().map(str::trim);
+    let event_id = fields
+        .next()
+        .and_then(|x| x.parse::<usize>().ok())
+        .ok_or_else(|| ParseError { offset: 0, message: "invalid event id".to_string() })?;
+    let mut column = || fields.next().filter(|x| !x.is_empty()).map(str::to_string);
+    let (category, sub_category, outcome, channel, name) = (column(), column(), column(), column(), column());
+    let mapping = record
+        .iter()
+        .skip(6)
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            parse_mapping_column(c).map_err(|e| ParseError {
+                offset: e.offset,
+                message: format!("`{}`: {}", c, e.message),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(MappingRow { event_id, category, sub_category, outcome, channel, name, mapping })
+}
+
+/// Split a `Field:value` column of `event_mappings.csv` into
+/// its field and parsed value
+pub(crate) fn parse_mapping_column(column: &str) -> Result<(String, Option<Mapping>), ParseError> {
+    let mut components = column.splitn(2, ':').map(|x| x.replace("'", "").trim().to_string());
+    let field = components.next().unwrap_or_default();
+    let value = components.next().ok_or_else(|| ParseError {
+        offset: column.len(),
+        message: "expected `Field:value`".to_string(),
+    })?;
+    let mapping = parse_mapping_value(&field, &value)?;
+    Ok((field, mapping))
+}
+
+/// Parse the value mapped to a field, `None` if blank
+pub(crate) fn parse_mapping_value(field: &str, value: &str) -> Result<Option<Mapping>, ParseError> {
+    if value.trim().is_empty() {
+        Ok(None)
+    } else {
+        Mapping::parse(field, value).map(Some)
+    }
 }
 
 pub fn load_mapping() -> EventMapping {
     //! Loads a default Windows EventLog Native Mappings Config
     let event_mapping = include_str!("../../assets/event_mappings.csv");
+    let mut mapping = EventMapping::default();
     ReaderBuilder::new()
         .flexible(true)
         .has_headers(false)
         .from_reader(event_mapping.as_bytes())
         .records()
-        .filter(|result|result.is_ok())
-        .map(|result| {
-            let mut fields =
-                result
-                    .iter()
-                    .flatten()
-                    .filter_map(|r| if r.is_empty() { None } else { Some(r) });
-            let event_id = fields.next().and_then(|x| x.parse::<usize>().ok()).unwrap();
-            let category = fields.next().unwrap().to_string();
-            let sub_category = fields.next().unwrap().to_string();
-            let outcome = fields.next().unwrap().to_string();
-            let channel = fields.next().unwrap().to_string();
-            let name = fields.next().unwrap().to_string();
-            let mapping_info = fields
-                .map(|x| {
-                    let components = x.splitn(2, ':')
-                        .map(|x| x.replace("'", "").trim().to_string())
-                        .collect::<Vec<String>>();
-                    (components[0].to_string(), components[1].to_string())
-                })
-                .collect::<HashMap<String, String>>();
-            let e = WinEvent {
-                event_id,
-                category,
-                sub_category,
-                outcome,
-                channel,
-                name,
+        .filter_map(|result| result.ok())
+        .map(|record| {
+            let row = parse_mapping_row(&record)
+                .unwrap_or_else(|e| panic!("event mappings `{}`: {}", &record[0], e));
+            let column = |c: Option<String>| c.unwrap_or_else(|| "-".to_string());
+            let mapping_info = row
+                .mapping
+                .into_iter()
+                .filter_map(|(field, mapping)| Some((field, mapping?)))
+                .collect::<HashMap<String, Mapping>>();
+            WinEvent {
+                event_id: row.event_id,
+                category: column(row.category),
+                sub_category: column(row.sub_category),
+                outcome: column(row.outcome),
+                channel: column(row.channel),
+                name: column(row.name),
                 mapping_info: if mapping_info.is_empty() { None } else { Some(mapping_info) },
-            };
-            (event_id, e)
+            }
         })
-        .collect()
+        .for_each(|e| mapping.insert(e));
+
+    for (channel, providers) in CHANNEL_PROVIDERS {
+        for provider in providers.iter() {
+            mapping.provider_channels.push((provider.to_string(), channel.to_string()));
+        }
+    }
+    mapping
 }
 
+#[allow(dead_code)]
 pub trait EventMappingGetters {
-    fn get_info(&self, event_id: &usize) -> Option<&WinEvent>;
-    fn get_mapping_info(&self, event_id: &usize) -> Option<&HashMap<String, String>>;
-    fn get_name(&self, event_id: &usize) -> Option<&String>;
-    fn get_channel(&self, event_id: &usize) -> Option<&String>;
-    fn get_category(&self, event_id: &usize) -> Option<&String>;
-    fn get_sub_category(&self, event_id: &usize) -> Option<&String>;
-    fn get_outcome(&self, event_id: &usize) -> Option<&String>;
+    fn get_info(&self, source: &EventSource, event_id: usize) -> Option<&WinEvent>;
+    fn get_mapping_info(&self, source: &EventSource, event_id: usize) -> Option<&HashMap<String, Mapping>>;
+    fn get_name(&self, source: &EventSource, event_id: usize) -> Option<&String>;
+    fn get_channel(&self, source: &EventSource, event_id: usize) -> Option<&String>;
+    fn get_category(&self, source: &EventSource, event_id: usize) -> Option<&String>;
+    fn get_sub_category(&self, source: &EventSource, event_id: usize) -> Option<&String>;
+    fn get_outcome(&self, source: &EventSource, event_id: usize) -> Option<&String>;
 }
 
 impl EventMappingGetters for EventMapping {
-    fn get_info(&self, event_id: &usize) -> Option<&WinEvent> {
-        //! Get the Event Information
-        self.get(event_id).map(|x| x)
+    fn get_info(&self, source: &EventSource, event_id: usize) -> Option<&WinEvent> {
+        //! Get the Event Information.
+        //! Looks up the provider name, the provider GUID, the channel and
+        //! the channels the provider logs to, then falls back to the Event Id
+        let entries = self.by_source.get(&event_id).map(Vec::as_slice).unwrap_or_default();
+        let keys = [source.provider, source.guid, source.channel];
+        let keys = keys.iter().flatten().map(|s| source_key(s));
+        let known_channels = keys
+            .clone()
+            .filter_map(|k| find_source(&self.provider_channels, k))
+            .map(String::as_str);
+        keys.chain(known_channels)
+            .find_map(|k| find_source(entries, k))
+            .or_else(|| self.by_id.get(&event_id))
     }
 
-    fn get_mapping_info(&self, event_id: &usize) -> Option<&HashMap<String, String>> {
+    fn get_mapping_info(&self, source: &EventSource, event_id: usize) -> Option<&HashMap<String, Mapping>> {
         //! Get the Mapping Information of the Event
-        let x = self
-            .get(event_id)
-            .map(|x|x.mapping_info.as_ref());
-        if let Some(x) = x {
-            x
-        } else {
-            None
-        }
+        self.get_info(source, event_id)?.mapping_info.as_ref()
     }
 
-    fn get_name(&self, event_id: &usize) -> Option<&String> {
+    fn get_name(&self, source: &EventSource, event_id: usize) -> Option<&String> {
         //! Get the Name of the Event
-        self.get(event_id).map(|x| &x.name)
+        self.get_info(source, event_id).map(|x| &x.name)
     }
 
-    fn get_channel(&self, event_id: &usize) -> Option<&String> {
+    fn get_channel(&self, source: &EventSource, event_id: usize) -> Option<&String> {
         //! Get the Channel/Provider Source of the Event
-        self.get(event_id).map(|x| &x.channel)
+        self.get_info(source, event_id).map(|x| &x.channel)
     }
 
-    fn get_category(&self, event_id: &usize) -> Option<&String> {
+    fn get_category(&self, source: &EventSource, event_id: usize) -> Option<&String> {
         //! Get the Category of the Event
-        self.get(event_id).map(|x| &x.category)
+        self.get_info(source, event_id).map(|x| &x.category)
     }
 
-    fn get_sub_category(&self, event_id: &usize) -> Option<&String> {
+    fn get_sub_category(&self, source: &EventSource, event_id: usize) -> Option<&String> {
         //! Get the Sub Category of the Event
-        self.get(event_id).map(|x| &x.sub_category)
+        self.get_info(source, event_id).map(|x| &x.sub_category)
     }
 
-    fn get_outcome(&self, event_id: &usize) -> Option<&String> {
+    fn get_outcome(&self, source: &EventSource, event_id: usize) -> Option<&String> {
         //! Get the outcome (success/failure) of the Event
-        self.get(event_id).map(|x| &x.outcome)
+        self.get_info(source, event_id).map(|x| &x.outcome)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::mappers::{load_mapping, EventMappingGetters};
+    use csv::StringRecord;
+
+    use crate::mappers::{load_mapping, parse_mapping_row, EventMappingGetters, EventSource, MappingRegistry};
+
+    const SECURITY: EventSource = EventSource { provider: None, guid: None, channel: Some("Security") };
 
     #[test]
     fn test_mappings() {
         let event_map = load_mapping();
-        let event_name = event_map.get_info(&4760);
+        let event_name = event_map.get_info(&SECURITY, 4760);
         assert!(event_name.is_some());
         let event_name = event_name.unwrap();
         assert_eq!(event_name.name, "A security-disabled universal group was changed.".to_string());
         assert!(event_name.mapping_info.is_some());
         assert_eq!(event_name.channel, "Security")
     }
+
+    #[test]
+    fn test_mappings_by_source() {
+        let event_map = load_mapping();
+        let defender = EventSource {
+            provider: Some("Microsoft-Windows-Windows Defender"),
+            channel: Some("Microsoft-Windows-Windows Defender/Operational"),
+            ..Default::default()
+        };
+        let e = event_map.get_info(&defender, 1151).unwrap();
+        assert_eq!(e.name, "Endpoint Protection client health report (time in UTC)");
+        assert!(event_map.get_info(&SECURITY, 1151).is_none());
+        assert!(event_map.get_info(&defender, 4624).is_none());
+        // Without a known source, only the Event Id only mappings apply
+        assert!(event_map.get_info(&EventSource::default(), 4624).is_none());
+    }
+
+    #[test]
+    fn test_parse_mapping_row() {
+        // Columns are taken by position, blank ones included
+        let record = StringRecord::from(vec!["4624", "", "Logon", " ", "", "Logged on", "Message:", " Source User Name:A"]);
+        let row = parse_mapping_row(&record).unwrap();
+        assert_eq!(row.event_id, 4624);
+        assert_eq!(row.category, None);
+        assert_eq!(row.sub_category.as_deref(), Some("Logon"));
+        assert_eq!(row.name.as_deref(), Some("Logged on"));
+        assert_eq!(row.mapping.len(), 2);
+        assert_eq!(row.mapping[0].0, "Message");
+        assert!(row.mapping[0].1.is_none());
+        assert_eq!(row.mapping[1].1.as_ref().unwrap().to_string(), "A");
+
+        let record = StringRecord::from(vec!["4624", "", "", "", "", "", "Source User Name:one_of(A"]);
+        let e = parse_mapping_row(&record).err().unwrap();
+        assert_eq!(e.to_string(), "`Source User Name:one_of(A`: unexpected end of mapping at column 9");
+    }
+
+    #[test]
+    fn test_registry_is_shared() {
+        let (a, b) = (MappingRegistry::default(), MappingRegistry::default());
+        assert!(std::sync::Arc::ptr_eq(&a.events, &b.events));
+        assert_eq!(MappingRegistry::builtin().events.len(), load_mapping().len());
+    }
 }
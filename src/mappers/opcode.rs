@@ -1,12 +1,14 @@
 use serde::{Deserialize, Deserializer};
 
-pub(crate) fn opcode_map<'de, D>(deserializer: D) -> Result<String, D::Error>
+use crate::de::Opcode;
+
+pub(crate) fn opcode_map<'de, D>(deserializer: D) -> Result<Opcode, D::Error>
     where
         D: Deserializer<'de>,
 {
-    usize::deserialize(deserializer).map(|x| {
+    u8::deserialize(deserializer).map(|x| {
         let ustr = x.to_string();
-        match x {
+        let name = match x {
             0 => "Info",
             1 => "Start",
             2 => "Stop",
@@ -19,7 +21,7 @@ pub(crate) fn opcode_map<'de, D>(deserializer: D) -> Result<String, D::Error>
             9 => "Send",
             240 => "Receive",
             _ => &ustr
-        }
-            .into()
+        };
+        Opcode { value: x, name: name.into() }
     })
 }
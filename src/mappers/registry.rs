@@ -0,0 +1,474 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use csv::{Position, ReaderBuilder};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    mappers::{
+        cef_map::CefMap, load_mapping, parse_mapping_row, parse_mapping_value, tasks, EventMapping, Mapping,
+        WinEvent,
+    },
+};
+
+lazy_static! {
+    static ref DEFAULT: MappingRegistry = MappingRegistry {
+        events: Arc::new(load_mapping()),
+        cef: Arc::new(CefMap::load_cef_map()),
+        tasks: Arc::new(tasks::load_tasks()),
+    };
+}
+
+/// The mapping tables used to name events, tasks and CEF fields.
+///
+/// The built-in tables are parsed once and shared; a registry built with
+/// [`MappingRegistry::builder`] layers user mapping files on top of them.
+/// Cloning a registry is cheap.
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file_with, MappingRegistry, ToCEF};
+///
+/// let registry = MappingRegistry::builder()
+///     .event_mappings_csv("4624,,,,,Somebody logged on,Device Custom String 1:LogonType")
+///     .unwrap()
+///     .build();
+/// let e = from_file_with("data/winevt1.xml", &registry).unwrap();
+/// assert_eq!(e.System.Event.EventName, "Somebody logged on");
+/// assert!(e.to_cef_with_mappings(&registry).contains("cs1=5"));
+/// ```
+#[derive(Clone)]
+pub struct MappingRegistry {
+    /// Event mappings keyed by source and Event Id
+    pub(crate) events: Arc<EventMapping>,
+    /// CEF field names
+    pub(crate) cef: Arc<CefMap>,
+    /// Task names keyed by Task Id
+    pub(crate) tasks: Arc<HashMap<usize, String>>,
+}
+
+impl Default for MappingRegistry {
+    fn default() -> Self {
+        DEFAULT.clone()
+    }
+}
+
+impl MappingRegistry {
+    /// Start a registry from the built-in mappings
+    pub fn builder() -> MappingRegistryBuilder {
+        MappingRegistryBuilder { registry: Self::default() }
+    }
+
+    /// The built-in registry, used by the conversions that don't take one
+    pub(crate) fn builtin() -> &'static Self {
+        &DEFAULT
+    }
+}
+
+/// Layers user mapping files over the built-in mappings.
+///
+/// Each layer overrides per Event Id and per field: columns (or keys) left
+/// empty keep the value of the layers below, mapping fields given with an
+/// empty value are removed. An override naming a source (channel, provider
+/// name or provider GUID) only applies to events from that source, one
+/// without a source applies to the Event Id whatever its source.
+pub struct MappingRegistryBuilder {
+    registry: MappingRegistry,
+}
+
+/// Layout of a TOML mapping file
+///
+/// ```toml
+/// [events.4624]
+/// name = "An account was successfully logged on"
+/// [events.4624.mapping]
+/// "Device Custom String 1" = "LogonType"
+///
+/// # Several sources sharing an Event Id
+/// [[events.1000]]
+/// provider = "Application Error"
+/// name = "Application crash"
+/// [[events.1000]]
+/// channel = "Microsoft-Windows-Windows Defender/Operational"
+/// name = "Malware scan started"
+///
+/// [cef]
+/// "Logon Type" = "cs1"
+///
+/// [tasks]
+/// 12544 = "Logon"
+/// ```
+#[derive(Deserialize, Default)]
+struct MappingFile {
+    #[serde(default)]
+    events: HashMap<String, EventOverrides>,
+    #[serde(default)]
+    cef: HashMap<String, String>,
+    #[serde(default)]
+    tasks: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EventOverrides {
+    One(EventOverride),
+    Many(Vec<EventOverride>),
+}
+
+#[derive(Deserialize, Default)]
+struct EventOverride {
+    provider: Option<String>,
+    category: Option<String>,
+    sub_category: Option<String>,
+    outcome: Option<String>,
+    channel: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    mapping: HashMap<String, String>,
+}
+
+impl MappingRegistryBuilder {
+    /// Layer event mappings in the `event_mappings.csv` format:
+    /// `event_id,category,sub_category,outcome,channel,name,Field:value,...`
+    ///
+    /// The channel column may also hold a provider name or GUID.
+    pub fn event_mappings_csv(mut self, csv: &str) -> Result<Self> {
+        let cef = &self.registry.cef;
+        let events = Arc::make_mut(&mut self.registry.events);
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_reader(csv.as_bytes());
+        for record in reader.records() {
+            // The reader skips blank lines, so rows are numbered from their position
+            let line_of = |p: Option<&Position>| p.map_or(0, |p| line_at(csv, p.byte() as usize));
+            let record = record.map_err(|e| mapping_error("event mappings", line_of(e.position()), e))?;
+            let line = line_of(record.position());
+            let row = parse_mapping_row(&record).map_err(|e| mapping_error("event mappings", line, e))?;
+            let o = EventOverride {
+                provider: None,
+                category: row.category,
+                sub_category: row.sub_category,
+                outcome: row.outcome,
+                channel: row.channel,
+                name: row.name,
+                mapping: HashMap::new(),
+            };
+            apply_event_override(events, cef, row.event_id, o, row.mapping)
+                .map_err(|e| mapping_error("event mappings", line, e))?;
+        }
+        Ok(self)
+    }
+
+    /// Layer CEF field names in the `cef_mapping.csv` format:
+    /// `cef_key,fullName,Display Name`
+    pub fn cef_mapping_csv(mut self, csv: &str) -> Result<Self> {
+        let cef = Arc::make_mut(&mut self.registry.cef);
+        for (line, row) in (1..).zip(csv.lines()).filter(|(_, l)| !l.trim().is_empty()) {
+            let components = row.split(',').map(|x| x.trim().to_string()).collect::<Vec<_>>();
+            if components.len() < 2 {
+                return Err(mapping_error("CEF mapping", line, "expected `key,fullName,...`"));
+            }
+            cef.insert(&components[0], &components[1..]);
+        }
+        Ok(self)
+    }
+
+    /// Layer task names in the `tasks.csv` format: `SYMBOL,task_id,Task Name`
+    pub fn tasks_csv(mut self, csv: &str) -> Result<Self> {
+        let tasks = Arc::make_mut(&mut self.registry.tasks);
+        for (line, row) in (1..).zip(csv.lines()).filter(|(_, l)| !l.trim().is_empty()) {
+            let components = row.split(',').map(|x| x.trim()).collect::<Vec<_>>();
+            let task_id = components
+                .get(1)
+                .and_then(|x| x.parse::<usize>().ok())
+                .ok_or_else(|| mapping_error("tasks", line, "invalid task id"))?;
+            let name = components
+                .get(2)
+                .ok_or_else(|| mapping_error("tasks", line, "missing task name"))?;
+            tasks.insert(task_id, name.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Layer a TOML mapping file holding any of `[events.<id>]`, `[cef]` and `[tasks]`
+    pub fn toml(mut self, text: &str) -> Result<Self> {
+        let file: MappingFile = toml::from_str(text).map_err(|e| Error::Mapping {
+            source: "TOML mapping".to_string(),
+            message: e.to_string(),
+        })?;
+
+        // CEF fields first, event mappings may refer to the ones added here
+        let cef = Arc::make_mut(&mut self.registry.cef);
+        for (field, key) in file.cef {
+            cef.insert(&key, &[field]);
+        }
+        let cef = &self.registry.cef;
+        let events = Arc::make_mut(&mut self.registry.events);
+        for (id, overrides) in file.events {
+            let event_id = id.parse::<usize>().map_err(|_| Error::Mapping {
+                source: "TOML mapping".to_string(),
+                message: format!("invalid event id `{}`", id),
+            })?;
+            let overrides = match overrides {
+                EventOverrides::One(o) => vec![o],
+                EventOverrides::Many(v) => v,
+            };
+            for mut o in overrides {
+                let error = |message: String| Error::Mapping {
+                    source: "TOML mapping".to_string(),
+                    message: format!("event {}: {}", event_id, message),
+                };
+                let mapping = o
+                    .mapping
+                    .drain()
+                    .map(|(k, v)| match parse_mapping_value(&k, &v) {
+                        Ok(m) => Ok((k, m)),
+                        Err(e) => Err(error(format!("`{}:{}`: {}", k, v, e))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                apply_event_override(events, cef, event_id, o, mapping).map_err(error)?;
+            }
+        }
+        let tasks = Arc::make_mut(&mut self.registry.tasks);
+        for (id, name) in file.tasks {
+            let task_id = id.parse::<usize>().map_err(|_| Error::Mapping {
+                source: "TOML mapping".to_string(),
+                message: format!("invalid task id `{}`", id),
+            })?;
+            tasks.insert(task_id, name);
+        }
+        Ok(self)
+    }
+
+    /// Layer an event mappings CSV file
+    pub fn event_mappings_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        self.event_mappings_csv(&read(path)?)
+    }
+
+    /// Layer a CEF mapping CSV file
+    pub fn cef_mapping_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        self.cef_mapping_csv(&read(path)?)
+    }
+
+    /// Layer a tasks CSV file
+    pub fn tasks_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        self.tasks_csv(&read(path)?)
+    }
+
+    /// Layer a TOML mapping file
+    pub fn toml_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        self.toml(&read(path)?)
+    }
+
+    /// Get the resulting registry
+    pub fn build(self) -> MappingRegistry {
+        self.registry
+    }
+}
+
+/// Apply an override and its parsed mapping, failing with a message on an unknown CEF field
+fn apply_event_override(
+    events: &mut EventMapping,
+    cef: &CefMap,
+    event_id: usize,
+    o: EventOverride,
+    mapping: Vec<(String, Option<Mapping>)>,
+) -> std::result::Result<(), String> {
+    if let Some((k, _)) = mapping.iter().find(|(k, _)| !cef.is_known(k)) {
+        return Err(format!("unknown CEF field `{}`", k));
+    }
+
+    let blank = |channel: &str| WinEvent {
+        event_id,
+        category: "-".to_string(),
+        sub_category: "-".to_string(),
+        outcome: "-".to_string(),
+        channel: channel.to_string(),
+        name: format!("EventID-{}", event_id),
+        mapping_info: None,
+    };
+    // Without a source, the override applies to the event from every source
+    let targets = match o.provider.as_ref().or(o.channel.as_ref()) {
+        Some(source) => {
+            if events.get_mut(source, event_id).is_none() {
+                events.insert(blank(source));
+            }
+            events.get_mut(source, event_id).into_iter().collect::<Vec<_>>()
+        }
+        None => {
+            if events.entries_mut(event_id).next().is_none() {
+                events.insert(blank("-"));
+            }
+            events.entries_mut(event_id).collect()
+        }
+    };
+    for e in targets {
+        let fields = [
+            (&mut e.category, &o.category),
+            (&mut e.sub_category, &o.sub_category),
+            (&mut e.outcome, &o.outcome),
+            (&mut e.name, &o.name),
+        ];
+        for (field, value) in fields {
+            if let Some(v) = value {
+                *field = v.to_string();
+            }
+        }
+        if !mapping.is_empty() {
+            let mapping_info = e.mapping_info.get_or_insert_with(HashMap::new);
+            for (k, m) in &mapping {
+                match m {
+                    Some(m) => mapping_info.insert(k.to_string(), m.clone()),
+                    None => mapping_info.remove(k),
+                };
+            }
+            if mapping_info.is_empty() {
+                e.mapping_info = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read<P: AsRef<Path>>(path: P) -> Result<String> {
+    fs::read_to_string(path.as_ref()).map_err(|e| Error::io(&path, e))
+}
+
+/// Line number of the row starting at `byte`, past the blank lines before it
+fn line_at(text: &str, byte: usize) -> u64 {
+    let rest = text[byte..].trim_start_matches(['\r', '\n']);
+    text[..text.len() - rest.len()].matches('\n').count() as u64 + 1
+}
+
+fn mapping_error<E: ToString>(source: &str, line: u64, e: E) -> Error {
+    Error::Mapping {
+        source: format!("{} (line {})", source, line),
+        message: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mappers::{EventMappingGetters, EventSource};
+
+    const SECURITY: EventSource = EventSource { provider: None, guid: None, channel: Some("Security") };
+
+    #[test]
+    fn test_event_override_per_field() {
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv("4760,,,,,Renamed,Destination User Name:TargetUserName,Device Action:")
+            .unwrap()
+            .build();
+        let e = registry.events.get_info(&SECURITY, 4760).unwrap();
+        assert_eq!(e.name, "Renamed");
+        assert_eq!(e.channel, "Security");
+        let mapping = e.mapping_info.as_ref().unwrap();
+        assert_eq!(mapping["Destination User Name"].to_string(), "TargetUserName");
+        assert!(!mapping.contains_key("Device Action"));
+        assert!(mapping.contains_key("Destination NT Domain"));
+
+        // The defaults are left untouched
+        let default = MappingRegistry::default();
+        assert_ne!(default.events.get_name(&SECURITY, 4760).unwrap(), "Renamed");
+    }
+
+    #[test]
+    fn test_toml_layers() {
+        let registry = MappingRegistry::builder()
+            .toml(
+                r#"
+                [events.9999]
+                name = "Custom event"
+                [events.9999.mapping]
+                "Logon Type" = "LogonType"
+
+                [cef]
+                "Logon Type" = "cs1"
+
+                [tasks]
+                1 = "Custom task"
+                "#,
+            )
+            .unwrap()
+            .build();
+        assert_eq!(registry.events.get_name(&EventSource::default(), 9999).unwrap(), "Custom event");
+        assert_eq!(registry.cef.get_cef_field("Logon Type").unwrap(), "cs1");
+        assert_eq!(registry.tasks[&1], "Custom task");
+    }
+
+    #[test]
+    fn test_override_by_source() {
+        let registry = MappingRegistry::builder()
+            .event_mappings_csv("1151,,,,Contoso,Contoso health report")
+            .unwrap()
+            .toml(
+                r#"
+                [[events.4624]]
+                provider = "Contoso"
+                name = "Contoso logon"
+                [[events.4624]]
+                channel = "Security"
+                outcome = "Success"
+                "#,
+            )
+            .unwrap()
+            .build();
+        let contoso = EventSource { provider: Some("Contoso"), channel: Some("Application"), ..Default::default() };
+        assert_eq!(registry.events.get_name(&contoso, 1151).unwrap(), "Contoso health report");
+        assert_eq!(registry.events.get_name(&contoso, 4624).unwrap(), "Contoso logon");
+
+        // The Defender and Security mappings are left untouched
+        let defender = EventSource {
+            channel: Some("Microsoft-Windows-Windows Defender/Operational"),
+            ..Default::default()
+        };
+        assert_eq!(
+            registry.events.get_name(&defender, 1151).unwrap(),
+            "Endpoint Protection client health report (time in UTC)"
+        );
+        let e = registry.events.get_info(&SECURITY, 4624).unwrap();
+        assert_eq!(e.name, "An account was successfully logged on.");
+        assert_eq!(e.outcome, "Success");
+    }
+
+    #[test]
+    fn test_invalid_rows() {
+        let e = MappingRegistry::builder().event_mappings_csv("abc,x").err().unwrap();
+        assert_eq!(e.kind(), crate::ErrorKind::Mapping);
+        assert!(MappingRegistry::builder().tasks_csv("X,notanumber,Y").is_err());
+        assert!(MappingRegistry::builder().toml("[events.x]").is_err());
+
+        // Malformed mapping expressions are reported with their row
+        let e = MappingRegistry::builder()
+            .event_mappings_csv("4624,,,,,,Source User Name:one_of(A;B")
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "invalid event mappings (line 1): `Source User Name:one_of(A;B`: unexpected end of mapping at column 11"
+        );
+        let e = MappingRegistry::builder()
+            .toml("[events.4624.mapping]\n\"Source User Name\" = \"some_of(A)\"")
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), crate::ErrorKind::Mapping);
+
+        // Rows are numbered as lines of the file, blank lines included
+        let e = MappingRegistry::builder()
+            .event_mappings_csv("4624,,,,,Logon\n\n4625,,,,,,Source Usr Name:TargetUserName")
+            .err()
+            .unwrap();
+        assert_eq!(
+            e.to_string(),
+            "invalid event mappings (line 3): unknown CEF field `Source Usr Name`"
+        );
+        let e = MappingRegistry::builder()
+            .toml("[events.4624.mapping]\n\"Logon Typ\" = \"LogonType\"")
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "invalid TOML mapping: event 4624: unknown CEF field `Logon Typ`");
+    }
+}
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
 
+use crate::{de::Task, mappers::MappingRegistry};
+
 fn get_map(
     text_str: &str,
     line_break: char, string_break: char,
@@ -26,31 +28,35 @@ fn get_map(
         .collect()
 }
 
-fn get_sa_tasks_mapping(task_id: usize) -> String {
+/// Load the Security Auditing task names, keyed by Task Id
+pub(crate) fn load_tasks() -> HashMap<usize, String> {
     let tasks = include_str!("../../assets/tasks.csv").trim();
-    let tasks_map: HashMap<usize, String> = get_map(
-        tasks, '\n', ',', (1,2)
-    );
+    get_map(tasks, '\n', ',', (1,2))
+}
 
-    if let Some(task_name) = tasks_map.get(&task_id) {
+fn get_sa_tasks_mapping(registry: &MappingRegistry, task_id: usize) -> String {
+    if let Some(task_name) = registry.tasks.get(&task_id) {
         task_name.to_string()
     } else {
         format!("({})", task_id)
     }
 }
 
-pub(crate) fn tasks_map<'de, D>(deserializer: D) -> Result<String, D::Error>
+pub(crate) fn tasks_map<'de, D>(deserializer: D) -> Result<Task, D::Error>
     where
         D: Deserializer<'de>,
 {
-    usize::deserialize(deserializer).map(|x| {
-        let mapped = get_sa_tasks_mapping(x);
-        match x {
-            0 => "None",
-            _ => &mapped
-        }
-            .into()
-    })
+    //! The name depends on the mapping registry in use: it is filled in
+    //! by `name_task` once the whole event is deserialized
+    u16::deserialize(deserializer).map(|x| Task { value: x, name: String::new() })
+}
+
+pub(crate) fn name_task(task: &mut Task, registry: &MappingRegistry) {
+    //! Name the task from its Task Id
+    task.name = match task.value {
+        0 => "None".into(),
+        x => get_sa_tasks_mapping(registry, x.into()),
+    };
 }
 
 #[cfg(test)]
@@ -59,9 +65,13 @@ mod tests {
 
     #[test]
     fn test_sa_tasks_map() {
-        let evt = get_sa_tasks_mapping(12289);
+        let registry = MappingRegistry::builtin();
+        let evt = get_sa_tasks_mapping(registry, 12289);
         assert_eq!(evt, "Security System Extension");
-        let evt = get_sa_tasks_mapping(1);
-        assert_eq!(evt, "(1)")
+        let evt = get_sa_tasks_mapping(registry, 1);
+        assert_eq!(evt, "(1)");
+
+        let registry = MappingRegistry::builder().tasks_csv("X,1,Custom").unwrap().build();
+        assert_eq!(get_sa_tasks_mapping(&registry, 1), "Custom");
     }
 }
@@ -0,0 +1,292 @@
+use serde_json::{Map, Value};
+
+use crate::{
+    Event,
+    cef::parser::get_event_outcome,
+    de::Level,
+    ecs::set,
+    mappers::{EventMappingGetters, MappingRegistry},
+    time::TimeFormat,
+};
+
+/// Version of the OCSF schema the events are shaped after
+const OCSF_VERSION: &str = "1.1.0";
+
+/// An OCSF event class
+struct Class {
+    uid: usize,
+    name: &'static str,
+    category_uid: usize,
+    category_name: &'static str,
+}
+
+const BASE_EVENT: Class = Class { uid: 0, name: "Base Event", category_uid: 0, category_name: "Uncategorized" };
+const PROCESS_ACTIVITY: Class = Class { uid: 1007, name: "Process Activity", category_uid: 1, category_name: "System Activity" };
+const ACCOUNT_CHANGE: Class = Class { uid: 3001, name: "Account Change", category_uid: 3, category_name: "Identity & Access Management" };
+const AUTHENTICATION: Class = Class { uid: 3002, name: "Authentication", category_uid: 3, category_name: "Identity & Access Management" };
+
+/// Security events with an OCSF class, with their activity id and name
+const CLASSES: [(usize, &Class, usize, &str); 4] = [
+    (4624, &AUTHENTICATION, 1, "Logon"),
+    (4625, &AUTHENTICATION, 1, "Logon"),
+    (4688, &PROCESS_ACTIVITY, 1, "Launch"),
+    (4720, &ACCOUNT_CHANGE, 1, "Create"),
+];
+
+pub trait ToOCSF {
+    /// Converts into an Open Cybersecurity Schema Framework (OCSF) event
+    fn to_ocsf(&self) -> Value;
+
+    /// Converts into an OCSF event, looking the event up
+    /// in the given registry instead of the built-in one
+    fn to_ocsf_with_mappings(&self, registry: &MappingRegistry) -> Value;
+}
+
+impl ToOCSF for Event {
+    fn to_ocsf(&self) -> Value {
+        ocsf_event(self, MappingRegistry::builtin())
+    }
+
+    fn to_ocsf_with_mappings(&self, registry: &MappingRegistry) -> Value {
+        ocsf_event(self, registry)
+    }
+}
+
+fn ocsf_event(evt: &Event, registry: &MappingRegistry) -> Value {
+    //! Converts Windows Event into
+    //! an Open Cybersecurity Schema Framework (OCSF) event.
+    //! Unmapped Security events, and all others, are Base Events
+    let system = &evt.System;
+    let event_id = system.Event.EventID;
+    let is_security = registry
+        .events
+        .get_channel(&system.source(), event_id)
+        .is_some_and(|c| c == "Security");
+    let (class, activity_id, activity_name) = CLASSES
+        .iter()
+        .find(|(id, ..)| *id == event_id && is_security)
+        .map(|(_, class, activity_id, activity_name)| (*class, *activity_id, *activity_name))
+        .unwrap_or((&BASE_EVENT, 0, "Unknown"));
+    let (status_id, status) = status(evt, registry);
+    let (severity_id, severity) = severity(&system.Level);
+    let data = |name: &str| -> Option<String> {
+        evt.EventData
+            .as_ref()
+            .and_then(|d| d.Data.get(name))
+            .map(str::to_string)
+    };
+
+    let mut doc = Map::new();
+    set(&mut doc, "class_uid", class.uid);
+    set(&mut doc, "class_name", class.name);
+    set(&mut doc, "category_uid", class.category_uid);
+    set(&mut doc, "category_name", class.category_name);
+    set(&mut doc, "activity_id", activity_id);
+    set(&mut doc, "activity_name", activity_name);
+    set(&mut doc, "type_uid", class.uid * 100 + activity_id);
+    set(&mut doc, "status_id", status_id);
+    set(&mut doc, "status", status);
+    set(&mut doc, "severity_id", severity_id);
+    set(&mut doc, "severity", severity);
+    set(&mut doc, "time", system.TimeCreated.timestamp_millis());
+    set(&mut doc, "message", system.Event.EventName.as_str());
+
+    set(&mut doc, "metadata.version", OCSF_VERSION);
+    set(&mut doc, "metadata.product.name", "Windows");
+    set(&mut doc, "metadata.product.vendor_name", system.DeviceVendor.as_str());
+    set(&mut doc, "metadata.log_name", system.Channel.as_str());
+    set(&mut doc, "metadata.log_provider", system.Provider.as_ref().and_then(|p| p.Name.as_deref()));
+    set(&mut doc, "metadata.uid", system.EventRecordID.to_string());
+    set(&mut doc, "metadata.event_code", event_id.to_string());
+    set(&mut doc, "metadata.original_time", TimeFormat::Rfc3339.format(&system.TimeCreated));
+    set(&mut doc, "metadata.labels", labels(evt, registry));
+
+    set(&mut doc, "device.hostname", system.Computer.as_str());
+    set(&mut doc, "device.type_id", 0);
+
+    set(&mut doc, "actor.user.name", data("SubjectUserName"));
+    set(&mut doc, "actor.user.domain", data("SubjectDomainName"));
+    set(&mut doc, "actor.user.uid", data("SubjectUserSid"));
+    set(&mut doc, "actor.session.uid", data("SubjectLogonId"));
+
+    match class.uid {
+        3001 | 3002 => {
+            set(&mut doc, "user.name", data("TargetUserName"));
+            set(&mut doc, "user.domain", data("TargetDomainName"));
+            set(&mut doc, "user.uid", data("TargetUserSid").or_else(|| data("TargetSid")));
+        }
+        _ => {}
+    }
+    match class.uid {
+        3002 => {
+            let logon_type = data("LogonType").and_then(|t| t.parse::<usize>().ok());
+            set(&mut doc, "logon_type_id", logon_type);
+            set(&mut doc, "auth_protocol", data("AuthenticationPackageName"));
+            set(&mut doc, "logon_process.pid", data("ProcessId").and_then(|p| parse_pid(&p)));
+            set(&mut doc, "logon_process.file.path", data("ProcessName"));
+            set(&mut doc, "src_endpoint.ip", data("IpAddress"));
+            set(&mut doc, "src_endpoint.port", data("IpPort").and_then(|p| p.parse::<u16>().ok()).filter(|p| *p != 0));
+            set(&mut doc, "src_endpoint.hostname", data("WorkstationName"));
+            set(&mut doc, "status_detail", data("FailureReason"));
+            set(&mut doc, "status_code", data("SubStatus").or_else(|| data("Status")));
+        }
+        1007 => {
+            set(&mut doc, "process.pid", data("NewProcessId").and_then(|p| parse_pid(&p)));
+            set(&mut doc, "process.file.path", data("NewProcessName"));
+            set(&mut doc, "process.cmd_line", data("CommandLine"));
+            set(&mut doc, "actor.process.pid", data("ProcessId").and_then(|p| parse_pid(&p)));
+            set(&mut doc, "actor.process.file.path", data("ParentProcessName"));
+        }
+        _ => {}
+    }
+
+    set(&mut doc, "unmapped.event_data", evt.EventData.as_ref().map(|d| Value::Object(d.Data.to_object())));
+    Value::Object(doc)
+}
+
+/// Get the OCSF status id and name of the event: from the outcome
+/// of its mapping, or from its keywords if it may be either
+fn status(evt: &Event, registry: &MappingRegistry) -> (usize, &'static str) {
+    let outcome = registry
+        .events
+        .get_outcome(&evt.System.source(), evt.System.Event.EventID)
+        .cloned()
+        .unwrap_or_default();
+    let outcome = match outcome.trim() {
+        "Success" => "/Success".to_string(),
+        "Failure" => "/Failure".to_string(),
+        _ => get_event_outcome(&evt.System.Keywords),
+    };
+    match outcome.as_str() {
+        "/Success" => (1, "Success"),
+        "/Failure" => (2, "Failure"),
+        _ => (0, "Unknown"),
+    }
+}
+
+/// Get the OCSF severity id and name of an event level
+fn severity(level: &Level) -> (usize, &'static str) {
+    match level.value {
+        0 | 4 | 5 => (1, "Informational"),
+        3 => (3, "Medium"),
+        2 => (4, "High"),
+        1 => (5, "Critical"),
+        _ => (0, "Unknown"),
+    }
+}
+
+/// The category and sub category of the event, as mapped
+fn labels(evt: &Event, registry: &MappingRegistry) -> Value {
+    let source = evt.System.source();
+    let id = evt.System.Event.EventID;
+    [registry.events.get_category(&source, id), registry.events.get_sub_category(&source, id)]
+        .iter()
+        .flatten()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty() && *s != "-")
+        .map(|s| Value::String(s.to_string()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Parse a process id, written in hex (eg: `0x250`) in the Security events
+fn parse_pid(pid: &str) -> Option<u64> {
+    match pid.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => pid.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_file, from_string};
+
+    /// winevt1 (4624) turned into another Security event
+    fn security_event(event_id: usize, data: &str) -> Event {
+        let xml = std::fs::read_to_string("data/winevt1.xml").unwrap();
+        let start = xml.find("<EventData>").unwrap();
+        let end = xml.find("</EventData>").unwrap();
+        let xml = format!("{}<EventData>{}{}", &xml[..start], data, &xml[end..])
+            .replace("<EventID>4624<", &format!("<EventID>{}<", event_id));
+        from_string(xml).unwrap()
+    }
+
+    #[test]
+    fn test_authentication() {
+        let ocsf = from_file("data/winevt1.xml").unwrap().to_ocsf();
+        assert_eq!(ocsf["class_uid"], 3002);
+        assert_eq!(ocsf["category_uid"], 3);
+        assert_eq!(ocsf["activity_id"], 1);
+        assert_eq!(ocsf["type_uid"], 300201);
+        assert_eq!(ocsf["status_id"], 1);
+        assert_eq!(ocsf["severity_id"], 1);
+        assert_eq!(ocsf["time"], 1611659849485i64);
+        assert_eq!(ocsf["metadata"]["labels"], serde_json::json!(["Logon/Logoff", "Logon"]));
+        assert_eq!(ocsf["device"]["hostname"], "DESKTOP-G089JUF");
+        assert_eq!(ocsf["actor"]["user"]["name"], "DESKTOP-G089JUF$");
+        assert_eq!(ocsf["user"]["name"], "SYSTEM");
+        assert_eq!(ocsf["user"]["uid"], "S-1-5-18");
+        assert_eq!(ocsf["logon_type_id"], 5);
+        assert_eq!(ocsf["logon_process"]["pid"], 0x250);
+        assert_eq!(ocsf["src_endpoint"]["ip"], "138.36.107.50");
+
+        let e = security_event(
+            4625,
+            r#"<Data Name="TargetUserName">bob</Data>
+               <Data Name="FailureReason">%%2313</Data>
+               <Data Name="Status">0xc000006d</Data>
+               <Data Name="SubStatus">0xc000006a</Data>"#,
+        );
+        let ocsf = e.to_ocsf();
+        assert_eq!(ocsf["class_uid"], 3002);
+        assert_eq!(ocsf["status_id"], 2);
+        assert_eq!(ocsf["status"], "Failure");
+        assert_eq!(ocsf["status_code"], "0xc000006a");
+        assert_eq!(ocsf["user"]["name"], "bob");
+    }
+
+    #[test]
+    fn test_process_and_account() {
+        let e = security_event(
+            4688,
+            r#"<Data Name="SubjectUserName">alice</Data>
+               <Data Name="NewProcessId">0x1a2c</Data>
+               <Data Name="NewProcessName">C:\Windows\System32\cmd.exe</Data>
+               <Data Name="CommandLine">cmd.exe /c whoami</Data>
+               <Data Name="ProcessId">0x250</Data>"#,
+        );
+        let ocsf = e.to_ocsf();
+        assert_eq!(ocsf["class_uid"], 1007);
+        assert_eq!(ocsf["category_uid"], 1);
+        assert_eq!(ocsf["activity_name"], "Launch");
+        assert_eq!(ocsf["actor"]["user"]["name"], "alice");
+        assert_eq!(ocsf["process"]["pid"], 0x1a2c);
+        assert_eq!(ocsf["process"]["cmd_line"], "cmd.exe /c whoami");
+        assert_eq!(ocsf["actor"]["process"]["pid"], 0x250);
+        assert!(ocsf.get("user").is_none());
+
+        let e = security_event(4720, r#"<Data Name="TargetUserName">carol</Data>"#);
+        let ocsf = e.to_ocsf();
+        assert_eq!(ocsf["class_uid"], 3001);
+        assert_eq!(ocsf["activity_name"], "Create");
+        assert_eq!(ocsf["user"]["name"], "carol");
+    }
+
+    #[test]
+    fn test_base_event() {
+        let ocsf = from_file("data/winevt2.xml").unwrap().to_ocsf();
+        assert_eq!(ocsf["class_uid"], 0);
+        assert_eq!(ocsf["class_name"], "Base Event");
+        assert_eq!(ocsf["activity_id"], 0);
+        assert_eq!(ocsf["status_id"], 0);
+        assert_eq!(ocsf["metadata"]["log_name"], "System");
+        assert!(ocsf["metadata"].get("labels").is_none());
+
+        // A Security event id without a class of its own
+        let ocsf = security_event(4634, "").to_ocsf();
+        assert_eq!(ocsf["class_uid"], 0);
+        assert_eq!(ocsf["status_id"], 1);
+        assert_eq!(ocsf["metadata"]["labels"], serde_json::json!(["Logon/Logoff", "Logoff"]));
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    rc::Rc,
+};
+
+use quick_xml::{events::Event as XmlEvent, Reader, Writer};
+
+use crate::{
+    de::Event,
+    deserialize,
+    encoding,
+    error::{Error, Location, Result, Tracked},
+    mappers::MappingRegistry,
+};
+
+const EVENT_TAG: &[u8] = b"Event";
+
+/// Streaming reader over an XML export holding many `<Event>` elements,
+/// as produced by `wevtutil qe /f:xml` (bare sequence of events) or by
+/// Event Viewer "Save as XML" (events wrapped in an `<Events>` root).
+///
+/// Only one event is held in memory at a time.
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::EventReader;
+///
+/// let reader = EventReader::from_file("data/winevt1.xml").unwrap();
+/// for event in reader {
+///     let event = event.unwrap();
+///     println!("{:?}", event.System.Event);
+/// }
+/// ```
+pub struct EventReader<R: BufRead> {
+    reader: Reader<Tracked<R>>,
+    location: Rc<Cell<Location>>,
+    buf: Vec<u8>,
+    pending: Option<Error>,
+    registry: MappingRegistry,
+    done: bool,
+}
+
+impl EventReader<BufReader<File>> {
+    /// Open an XML export for streaming
+    pub fn from_file<P: AsRef<Path>>(xml_path: P) -> Result<Self> {
+        let f = File::open(xml_path.as_ref()).map_err(|e| Error::io(&xml_path, e))?;
+        Ok(Self::new(BufReader::new(f)))
+    }
+}
+
+impl<R: BufRead> EventReader<R> {
+    /// Stream events out of any buffered reader (eg: `std::io::stdin().lock()`)
+    pub fn new(mut reader: R) -> Self {
+        let pending = check_bom(&mut reader).err();
+        let tracked = Tracked::new(reader);
+        let location = tracked.location();
+        let mut reader = Reader::from_reader(tracked);
+        reader.check_end_names(true);
+        Self {
+            reader,
+            location,
+            buf: Vec::new(),
+            pending,
+            registry: MappingRegistry::default(),
+            done: false,
+        }
+    }
+
+    /// Name events and tasks with the given mapping registry
+    pub fn with_mappings(mut self, registry: &MappingRegistry) -> Self {
+        self.registry = registry.clone();
+        self
+    }
+
+    /// Skip ahead to the next `<Event>` element and return its serialized bytes
+    /// along with the location of its opening `<`.
+    fn next_event_xml(&mut self) -> Result<Option<(Vec<u8>, Location)>> {
+        let mut writer = Writer::new(Vec::new());
+        let mut depth = 0usize;
+        let mut start = Location::default();
+
+        loop {
+            self.buf.clear();
+            let location = &self.location;
+            // quick-xml has already read the `<` of the next tag, but at the very start
+            let mut before = location.get();
+            if before.offset > 0 {
+                before.offset -= 1;
+                before.column -= 1;
+            }
+            let evt = self.reader.read_event(&mut self.buf).map_err(|e| Error::Xml {
+                location: location.get(),
+                field: None,
+                message: e.to_string(),
+            })?;
+
+            match evt {
+                XmlEvent::Eof => return Ok(None),
+                XmlEvent::Decl(ref d) if depth == 0 => {
+                    let decl = format!("<?{}?>", String::from_utf8_lossy(d));
+                    encoding::check_declared_encoding(&decl)?;
+                    continue;
+                }
+                XmlEvent::Start(ref s) if depth == 0 && s.local_name() == EVENT_TAG => {
+                    start = before;
+                }
+                XmlEvent::Empty(ref s) if depth == 0 && s.local_name() == EVENT_TAG => {
+                    start = before;
+                }
+                _ if depth == 0 => continue,
+                _ => {}
+            }
+
+            match evt {
+                XmlEvent::Start(_) => depth += 1,
+                XmlEvent::End(_) => depth -= 1,
+                _ => {}
+            }
+            writer.write_event(&evt).map_err(|e| Error::Xml {
+                location: location.get(),
+                field: None,
+                message: e.to_string(),
+            })?;
+
+            if depth == 0 {
+                return Ok(Some((writer.into_inner(), start)));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for EventReader<R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(e) = self.pending.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+
+        match self.next_event_xml() {
+            Ok(Some((xml, start))) => {
+                let event = deserialize(xml.as_slice(), &self.registry);
+                Some(event.map_err(|e| relocate(e, start)))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // The stream itself is broken, there is no reliable way to resync
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Refuse UTF-16 input up front, it cannot be scanned as a byte stream.
+/// `from_file` decodes such exports as a whole instead.
+fn check_bom<R: BufRead>(reader: &mut R) -> Result<()> {
+    let head = reader.fill_buf()?;
+    if head.starts_with(&[0xFF, 0xFE]) || head.starts_with(&[0xFE, 0xFF]) {
+        return Err(Error::UnknownEncoding { encoding: "UTF-16".to_string() });
+    }
+    Ok(())
+}
+
+/// Rebase a location relative to a single event onto the whole stream.
+/// `start` is where the opening `<Event ...>` tag of that event starts.
+fn relocate(err: Error, start: Location) -> Error {
+    let shift = |loc: Location| Location {
+        offset: start.offset + loc.offset,
+        line: start.line + loc.line - 1,
+        // Only the first line of the event shares its line with what precedes it
+        column: if loc.line == 1 { start.column + loc.column - 1 } else { loc.column },
+    };
+    match err {
+        Error::Xml { location, field, message } => Error::Xml { location: shift(location), field, message },
+        Error::MissingField { location, field } => Error::MissingField { location: shift(location), field },
+        Error::InvalidNumber { location, field, message } => {
+            Error::InvalidNumber { location: shift(location), field, message }
+        }
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    fn export(wrapped: bool) -> String {
+        let events = (1..=4)
+            .map(|i| std::fs::read_to_string(format!("data/winevt{}.xml", i)).unwrap())
+            .map(|x| x.trim_start_matches(r#"<?xml version="1.0" encoding="utf-8"?>"#).to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if wrapped {
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Events>\n{}\n</Events>", events)
+        } else {
+            events
+        }
+    }
+
+    #[test]
+    fn test_bare_sequence() {
+        let xml = export(false);
+        let ids = EventReader::new(xml.as_bytes())
+            .map(|e| e.unwrap().System.Event.EventID)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![4624, 18, 15, 2]);
+    }
+
+    #[test]
+    fn test_events_root() {
+        let xml = export(true);
+        let ids = EventReader::new(xml.as_bytes())
+            .map(|e| e.unwrap().System.Event.EventID)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![4624, 18, 15, 2]);
+    }
+
+    #[test]
+    fn test_bad_event_does_not_stop_stream() {
+        let xml = export(true).replacen("<EventRecordID>5662<", "<EventRecordID>x<", 1);
+        let results = EventReader::new(xml.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results.len(), 4);
+        let err = results[1].as_ref().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidNumber);
+        assert_eq!(err.location().unwrap().line, 61);
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn test_one_line_events() {
+        let event = std::fs::read_to_string("data/winevt1.xml").unwrap();
+        let event = event
+            .trim_start_matches(r#"<?xml version="1.0" encoding="utf-8"?>"#)
+            .lines()
+            .map(str::trim)
+            .collect::<String>();
+        let broken = event.replace("<EventRecordID>26893<", "<EventRecordID>x<");
+        let xml = format!("<Events>{}{}</Events>", event, broken);
+        let results = EventReader::new(xml.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+
+        let err = results[1].as_ref().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidNumber);
+        let single = crate::from_string(broken.clone()).unwrap_err();
+        let (location, single) = (err.location().unwrap(), single.location().unwrap());
+        assert_eq!(location.line, 1);
+        // Shifted by what precedes the event on its line
+        let shift = "<Events>".len() + event.len();
+        assert_eq!(location.column, single.column + shift);
+        assert_eq!(location.offset, single.offset + shift);
+    }
+
+    #[test]
+    fn test_broken_stream() {
+        let xml = export(true).replacen("</Event>", "</Evnt>", 1);
+        let results = EventReader::new(xml.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap_err().kind(), ErrorKind::Xml);
+    }
+}
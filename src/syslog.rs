@@ -0,0 +1,237 @@
+use crate::{de::Level, Event};
+
+/// Syslog message format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyslogFormat {
+    /// RFC 5424, with structured data
+    Rfc5424,
+    /// RFC 3164 (BSD syslog), for legacy receivers
+    Rfc3164,
+}
+
+/// How messages are delimited in a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// No framing, one message per datagram (UDP)
+    None,
+    /// Each message ends with a line feed (non-transparent framing)
+    LineFeed,
+    /// Each message is prefixed by its length in bytes (RFC 6587 octet counting, TCP)
+    OctetCounting,
+}
+
+/// Options of the syslog wrapping
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, Framing, SyslogOptions, ToCEF, ToSyslog};
+///
+/// let opts = SyslogOptions { framing: Framing::OctetCounting, ..Default::default() };
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let msg = e.to_syslog_with(&e.to_cef(), &opts);
+/// assert!(msg.contains(" <14>1 2021-01-26T11:17:29.485696Z DESKTOP-G089JUF "));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyslogOptions {
+    /// Message format, RFC 5424 by default
+    pub format: SyslogFormat,
+    /// Stream framing, none by default
+    pub framing: Framing,
+    /// Facility of the PRI, `1` (user-level messages) by default.
+    /// Values past `23` (local7) are clamped to it
+    pub facility: u8,
+    /// SD-ID of the event structured data (RFC 5424 only),
+    /// `winevent@32473` by default. Set it empty to leave structured data out
+    pub sd_id: String,
+}
+
+impl Default for SyslogOptions {
+    fn default() -> Self {
+        Self {
+            format: SyslogFormat::Rfc5424,
+            framing: Framing::None,
+            facility: 1,
+            sd_id: "winevent@32473".to_string(),
+        }
+    }
+}
+
+pub trait ToSyslog {
+    /// Wraps a message (eg: the CEF, LEEF or JSON of the event)
+    /// in an RFC 5424 syslog message
+    fn to_syslog(&self, message: &str) -> String {
+        self.to_syslog_with(message, &SyslogOptions::default())
+    }
+
+    /// Wraps a message in a syslog message with the given options
+    fn to_syslog_with(&self, message: &str, opts: &SyslogOptions) -> String;
+}
+
+impl ToSyslog for Event {
+    fn to_syslog_with(&self, message: &str, opts: &SyslogOptions) -> String {
+        //! Wraps the message with a syslog header taken from the event:
+        //! PRI from `Level`, TIMESTAMP from `TimeCreated`,
+        //! HOSTNAME from `Computer` and APP-NAME from the provider
+        let system = &self.System;
+        let pri = opts.facility.min(23) as usize * 8 + severity(&system.Level) as usize;
+        let time = &system.TimeCreated;
+        let app_name = system
+            .Provider
+            .as_ref()
+            .and_then(|p| p.Name.as_deref())
+            .unwrap_or(&system.Channel);
+
+        let msg = match opts.format {
+            SyslogFormat::Rfc5424 => {
+                let structured_data = if opts.sd_id.is_empty() {
+                    "-".to_string()
+                } else {
+                    let params = [
+                        ("channel", system.Channel.to_string()),
+                        ("eventID", system.Event.EventID.to_string()),
+                        ("recordID", system.EventRecordID.to_string()),
+                        ("level", system.Level.to_string()),
+                        ("task", system.Task.to_string()),
+                        ("keywords", system.Keywords.to_string()),
+                    ]
+                        .iter()
+                        .map(|(k, v)| format!(" {}=\"{}\"", k, escape_param(v)))
+                        .collect::<String>();
+                    format!("[{}{}]", sd_name(&opts.sd_id), params)
+                };
+                format!(
+                    "<{pri}>1 {timestamp} {hostname} {app_name} {procid} {msgid} {sd} {msg}",
+                    pri = pri,
+                    timestamp = time.format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+                    hostname = header_field(&system.Computer, 255),
+                    app_name = header_field(app_name, 48),
+                    procid = system.Execution.ProcessID,
+                    msgid = system.Event.EventID,
+                    sd = structured_data,
+                    msg = message,
+                )
+            }
+            SyslogFormat::Rfc3164 => {
+                // The TAG is alphanumeric, `-` put aside
+                let tag = app_name
+                    .chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                    .take(32)
+                    .collect::<String>();
+                format!(
+                    "<{pri}>{timestamp} {hostname} {tag}[{pid}]: {msg}",
+                    pri = pri,
+                    timestamp = time.format("%b %e %H:%M:%S"),
+                    hostname = header_field(&system.Computer, 255),
+                    tag = tag,
+                    pid = system.Execution.ProcessID,
+                    msg = message,
+                )
+            }
+        };
+
+        match opts.framing {
+            Framing::None => msg,
+            Framing::LineFeed => format!("{}\n", msg.replace('\n', " ")),
+            Framing::OctetCounting => format!("{} {}", msg.len(), msg),
+        }
+    }
+}
+
+/// Get the syslog severity (0 to 7) of an event level
+pub(crate) fn severity(level: &Level) -> u8 {
+    match level.value {
+        1 => 2, // Critical
+        2 => 3, // Error
+        3 => 4, // Warning
+        5 => 7, // Verbose
+        _ => 6,
+    }
+}
+
+/// Make a header field printable ASCII without spaces, at most `max_len` long,
+/// or the NILVALUE `-` if empty
+fn header_field(value: &str, max_len: usize) -> String {
+    let field = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect::<String>();
+    if field.is_empty() { "-".to_string() } else { field }
+}
+
+/// Make an SD-ID: a header field without `=`, `]`, `"` nor spaces
+fn sd_name(value: &str) -> String {
+    let name = value
+        .chars()
+        .filter(|c| !matches!(c, '=' | ']' | '"' | ' '))
+        .collect::<String>();
+    header_field(&name, 32)
+}
+
+/// Escape a structured data parameter value: `"`, `\` and `]`
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_file;
+
+    #[test]
+    fn test_rfc5424() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        assert_eq!(
+            e.to_syslog("CEF:0|Microsoft"),
+            "<14>1 2021-01-26T11:17:29.485696Z DESKTOP-G089JUF Microsoft-Windows-Security-Auditing 648 4624 \
+             [winevent@32473 channel=\"Security\" eventID=\"4624\" recordID=\"26893\" level=\"Information\" \
+             task=\"Logon\" keywords=\"Audit Success\"] CEF:0|Microsoft"
+        );
+
+        // No structured data, escaping, NILVALUE and space free header fields
+        let mut e = from_file("data/winevt5.xml").unwrap();
+        e.System.Computer = String::new();
+        let opts = SyslogOptions { sd_id: String::new(), facility: 4, ..Default::default() };
+        let msg = e.to_syslog_with("{}", &opts);
+        assert!(msg.starts_with("<38>1 2021-01-29T13:55:33.274406Z - Microsoft-Windows-Windows_Defender "), "{}", msg);
+        assert!(msg.ends_with(" 1151 - {}"), "{}", msg);
+        assert_eq!(escape_param(r#"a"b\c]"#), r#"a\"b\\c\]"#);
+
+        // Out of range facilities and SD-ID characters
+        let opts = SyslogOptions { sd_id: "my id=\"x\"]@1".to_string(), facility: 200, ..Default::default() };
+        let msg = e.to_syslog_with("{}", &opts);
+        assert!(msg.starts_with("<190>1 "), "{}", msg);
+        assert!(msg.contains(" [myidx@1 channel="), "{}", msg);
+    }
+
+    #[test]
+    fn test_rfc3164() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let opts = SyslogOptions { format: SyslogFormat::Rfc3164, ..Default::default() };
+        assert_eq!(
+            e.to_syslog_with("msg", &opts),
+            // The TAG is cut to 32 characters
+            "<14>Jan 26 11:17:29 DESKTOP-G089JUF Microsoft-Windows-Security-Audit[648]: msg"
+        );
+    }
+
+    #[test]
+    fn test_framing() {
+        let e = from_file("data/winevt1.xml").unwrap();
+        let bare = e.to_syslog("é\nx");
+        let opts = SyslogOptions { framing: Framing::OctetCounting, ..Default::default() };
+        let framed = e.to_syslog_with("é\nx", &opts);
+        // Counts bytes, not characters
+        assert_eq!(framed, format!("{} {}", bare.len(), bare));
+        assert_eq!(bare.len(), bare.chars().count() + 1);
+
+        let opts = SyslogOptions { framing: Framing::LineFeed, ..Default::default() };
+        let framed = e.to_syslog_with("é\nx", &opts);
+        assert!(framed.ends_with(" é x\n"));
+    }
+}
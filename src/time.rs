@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::Serializer;
+use serde_json::Value;
+
+/// How timestamps are written
+///
+/// ## Example usage
+/// ```rust
+/// use winevents_xml_transform::{from_file, TimeFormat};
+///
+/// let e = from_file("data/winevt1.xml").unwrap();
+/// let time = &e.System.TimeCreated;
+/// assert_eq!(TimeFormat::Rfc3339.format(time), "2021-01-26T11:17:29.4856969Z");
+/// assert_eq!(TimeFormat::EpochSeconds.format(time), "1611659849");
+/// assert_eq!(TimeFormat::EpochMillis.format(time), "1611659849485");
+/// assert_eq!(TimeFormat::Cef.format(time), "Jan 26 2021 11:17:29.485");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// RFC 3339 in UTC, with the 100 ns precision of Windows
+    Rfc3339,
+    /// Seconds since the Unix epoch
+    EpochSeconds,
+    /// Milliseconds since the Unix epoch
+    EpochMillis,
+    /// CEF date, `MMM dd yyyy HH:mm:ss.SSS`
+    Cef,
+}
+
+impl TimeFormat {
+    /// Write a timestamp in this format
+    pub fn format(self, time: &DateTime<Utc>) -> String {
+        match self {
+            TimeFormat::Rfc3339 => format!(
+                "{}.{:07}Z",
+                time.format("%Y-%m-%dT%H:%M:%S"),
+                time.timestamp_subsec_nanos() / 100
+            ),
+            TimeFormat::EpochSeconds => time.timestamp().to_string(),
+            TimeFormat::EpochMillis => time.timestamp_millis().to_string(),
+            TimeFormat::Cef => time.format("%b %d %Y %H:%M:%S%.3f").to_string(),
+        }
+    }
+
+    /// Write a timestamp in this format as JSON: epoch times as numbers, the others as strings
+    pub(crate) fn to_json(self, time: &DateTime<Utc>) -> Value {
+        match self {
+            TimeFormat::EpochSeconds => time.timestamp().into(),
+            TimeFormat::EpochMillis => time.timestamp_millis().into(),
+            format => format.format(time).into(),
+        }
+    }
+}
+
+/// Serialize a timestamp in RFC 3339
+pub(crate) fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&TimeFormat::Rfc3339.format(time))
+}